@@ -0,0 +1,52 @@
+/* Generated by `solve_arrow_puzzle::capi::header`. Do not edit. */
+
+#ifndef SOLVE_ARROW_PUZZLE_H
+#define SOLVE_ARROW_PUZZLE_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* How many arrows a board has, and how many tap counts a solution has. */
+#define SOLVE_ARROW_PUZZLE_CELLS 37
+
+typedef enum SolveArrowPuzzleStatus {
+    /* Solved. */
+    SOLVE_ARROW_PUZZLE_OK = 0,
+    /* An arrow was 6 or more. Nothing was written. */
+    SOLVE_ARROW_PUZZLE_ARROW_OUT_OF_RANGE = 1,
+    /* A pointer was NULL. Nothing was written. */
+    SOLVE_ARROW_PUZZLE_NULL_POINTER = 2,
+} SolveArrowPuzzleStatus;
+
+/*
+ * Solves the board in `arrows` and writes how many times to tap each cell to
+ * `taps`, both in the order A0, A1, A2, A3, B0, ..., G6 of the cells on
+ *
+ *              A0
+ *           B0    A1
+ *        C0    B1    A2
+ *     D0    C1    B2    A3
+ *        D1    C2    B3
+ *     E1    D2    C3    B4
+ *        E2    D3    C4
+ *     F2    E3    D4    C5
+ *        F3    E4    D5
+ *     G3    F4    E5    D6
+ *        G4    F5    E6
+ *           G5    F6
+ *              G6
+ *
+ * Arrows are the number of clockwise turns from pointing up, within [0, 6).
+ */
+SolveArrowPuzzleStatus solve_arrow_puzzle_solve(
+    const uint8_t arrows[SOLVE_ARROW_PUZZLE_CELLS],
+    uint8_t taps[SOLVE_ARROW_PUZZLE_CELLS]);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SOLVE_ARROW_PUZZLE_H */
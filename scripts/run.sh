@@ -1,6 +1,6 @@
 #! zsh
 
-cargo run --release --bin main &
+cargo run --release --features cli &
 MAIN_PID=$!
 ./scripts/mirror.sh
 kill $MAIN_PID
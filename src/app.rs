@@ -1,27 +1,255 @@
 use std::{
+    error::Error as StdError,
+    fmt::Display,
+    io,
+    process::ExitStatus,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     thread::sleep,
     time::{Duration, Instant},
 };
 
-use anyhow::{bail, Context};
+use thiserror::Error;
 
-use crate::{expert::Board, hex::Hex};
+use crate::{
+    device::LayoutError,
+    expert::Board,
+    hex::Hex,
+    observer::{ErrorSource, PlayObserver},
+};
+
+/// Identifies a frame of the device's screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    /// Increases by one for every frame the device receives.
+    pub sequence: u64,
+    pub captured_at: Instant,
+}
+
+/// What the device's screen shows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScreenState {
+    /// The expert puzzle, solved or not, and whether the claim button for a
+    /// solved one is visible.
+    ExpertBoard {
+        board: Board,
+        claim_button: bool,
+    },
+    /// The normal puzzle, which can't be played, so it gets closed.
+    NormalBoard,
+    /// The claim button of a solved puzzle, without a readable board.
+    ClaimButton,
+    /// A dialog over the game, such as a level-up or a network error.
+    Popup,
+    Ad,
+    Unknown,
+}
+
+impl Display for ScreenState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScreenState::ExpertBoard {
+                board,
+                claim_button,
+            } => {
+                let solved = if board.is_solved() {
+                    "solved"
+                } else {
+                    "unsolved"
+                };
+                write!(f, "expert_board({}, claim_button={})", solved, claim_button)
+            }
+            ScreenState::NormalBoard => write!(f, "normal_board"),
+            ScreenState::ClaimButton => write!(f, "claim_button"),
+            ScreenState::Popup => write!(f, "popup"),
+            ScreenState::Ad => write!(f, "ad"),
+            ScreenState::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Detection {
+    pub frame: Frame,
+    pub screen: ScreenState,
+}
+
+/// Why talking to the device failed. Reconnecting usually helps.
+#[derive(Debug, Error)]
+pub enum DeviceError {
+    #[error("failed to {0}")]
+    Io(String, #[source] io::Error),
+    #[error("{0} failed: {1}")]
+    Command(&'static str, ExitStatus),
+    #[error("{0} exited: {1}")]
+    Exited(&'static str, ExitStatus),
+    /// A thread of the video pipeline failed, after which no more frames
+    /// come.
+    #[error("video pipeline stopped after failing to {action}")]
+    VideoStopped {
+        action: &'static str,
+        #[source]
+        source: Arc<DeviceError>,
+    },
+    #[error("failed to take the lock for {0}")]
+    Poisoned(&'static str),
+    /// The device came back, or a frame came, in an orientation or size that
+    /// the profile doesn't fit.
+    #[error("failed to lay out the screen")]
+    Layout(#[from] LayoutError),
+    /// The device sent something that makes no sense, or was asked to do
+    /// something it can't do yet.
+    #[error("{0}")]
+    Unexpected(String),
+    /// Anything else a [`Device`] runs into.
+    #[error("failed to {0}")]
+    Other(String, #[source] Box<dyn StdError + Send + Sync>),
+}
+
+/// Why the screen couldn't be detected.
+#[derive(Debug, Error)]
+pub enum DetectError {
+    #[error(transparent)]
+    Device(#[from] DeviceError),
+    /// The frame isn't what the device said it would be.
+    #[error("bad frame: {0}")]
+    Frame(String),
+}
+
+/// Frames that the profile doesn't fit fail the same way as reconnecting to a
+/// device that it doesn't fit, as [`DeviceError::Layout`].
+impl From<LayoutError> for DetectError {
+    fn from(err: LayoutError) -> DetectError {
+        DetectError::Device(err.into())
+    }
+}
+
+/// What the player gave up waiting for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitingFor {
+    Board,
+    UnsolvedBoard,
+}
+
+impl Display for WaitingFor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WaitingFor::Board => write!(f, "a board"),
+            WaitingFor::UnsolvedBoard => write!(f, "an unsolved board"),
+        }
+    }
+}
+
+/// Why [`play`] stopped, or what went wrong along the way.
+#[derive(Debug, Error)]
+pub enum PlayError {
+    #[error("waited for {0} for {1:?}")]
+    Timeout(WaitingFor, Duration),
+    #[error("the clock went backwards since the last transition")]
+    ClockWentBackwards,
+    #[error("failed to detect the screen")]
+    Detect(#[source] DetectError),
+    #[error("failed to {0}")]
+    Device(&'static str, #[source] DeviceError),
+    #[error("failed to reconnect after: {}", error_chain(.after.as_ref()))]
+    Reconnect {
+        #[source]
+        source: DeviceError,
+        after: Box<PlayError>,
+    },
+    #[error("gave up after {reconnects} reconnects")]
+    GaveUp {
+        reconnects: usize,
+        #[source]
+        last: Box<PlayError>,
+    },
+}
+
+/// `err` and all of its sources on one line, separated by colons.
+pub fn error_chain(err: &dyn StdError) -> String {
+    let mut chain = err.to_string();
+    let mut source = err.source();
+    while let Some(err) = source {
+        chain.push_str(": ");
+        chain.push_str(&err.to_string());
+        source = err.source();
+    }
+    chain
+}
 
 pub trait Device {
-    fn wait_duration() -> Duration;
-    fn detect_board(&mut self) -> anyhow::Result<Option<Board>>;
-    fn tap_board(&mut self, taps: Hex<usize>) -> anyhow::Result<()>;
-    fn tap_claim_button(&mut self) -> anyhow::Result<()>;
+    /// The clock that frames are captured by, and that the player times out
+    /// by.
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+    fn detect_screen(&mut self) -> Result<Detection, DetectError>;
+    /// Blocks until a frame newer than `frame` is available, or at most for
+    /// `timeout`, so that the player can still act on a screen that doesn't
+    /// change.
+    fn wait_for_next_frame(&mut self, frame: &Frame, timeout: Duration) -> Result<(), DeviceError>;
+    /// Blocks until a frame captured after `instant` is available. If the
+    /// screen doesn't change for a while, gives up within a second and
+    /// returns the latest frame, which is then still up to date.
+    fn wait_for_frame_after(&mut self, instant: Instant) -> Result<Frame, DeviceError>;
+    fn tap_board(&mut self, taps: Hex<usize>) -> Result<(), DeviceError>;
+    fn tap_claim_button(&mut self) -> Result<(), DeviceError>;
+    /// Presses the back button, which closes dialogs and ads.
+    fn press_back(&mut self) -> Result<(), DeviceError>;
+    /// Tears down the connection to the device and sets it up again, after
+    /// detecting or tapping failed.
+    fn reconnect(&mut self) -> Result<(), DeviceError>;
+}
+
+impl<D> Device for &mut D
+where
+    D: Device + ?Sized,
+{
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+
+    fn detect_screen(&mut self) -> Result<Detection, DetectError> {
+        (**self).detect_screen()
+    }
+
+    fn wait_for_next_frame(&mut self, frame: &Frame, timeout: Duration) -> Result<(), DeviceError> {
+        (**self).wait_for_next_frame(frame, timeout)
+    }
+
+    fn wait_for_frame_after(&mut self, instant: Instant) -> Result<Frame, DeviceError> {
+        (**self).wait_for_frame_after(instant)
+    }
+
+    fn tap_board(&mut self, taps: Hex<usize>) -> Result<(), DeviceError> {
+        (**self).tap_board(taps)
+    }
+
+    fn tap_claim_button(&mut self) -> Result<(), DeviceError> {
+        (**self).tap_claim_button()
+    }
+
+    fn press_back(&mut self) -> Result<(), DeviceError> {
+        (**self).press_back()
+    }
+
+    fn reconnect(&mut self) -> Result<(), DeviceError> {
+        (**self).reconnect()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum BoardState {
-    Solved,
+    Solved { claim_button: bool },
     Unsolved(Board),
 }
 
+/// What the player waits for next.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum PlayerState {
+#[allow(clippy::enum_variant_names)]
+pub enum PlayerState {
     WaitForBoard,
     WaitForSolvedBoard,
     WaitForUnsolvedBoard,
@@ -30,20 +258,36 @@ enum PlayerState {
 #[derive(Debug, Clone, PartialEq, Eq)]
 enum Action {
     Wait,
-    Solve(Board),
+    Poke(Box<Hex<usize>>),
     ClaimRewards,
+    GoBack,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 struct PlayerTransitionContext {
     now: Instant,
-    board: Option<Board>,
+    frame: Frame,
+    screen: ScreenState,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+/// The pokes last sent, and the board they were sent for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Plan {
+    pub board: Board,
+    pub pokes: Hex<usize>,
+    /// Whether these are only the pokes that got dropped from the last plan,
+    /// rather than ones that solve the board from scratch.
+    pub resend: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 struct Player {
     state: PlayerState,
     last_transition: Instant,
+    /// How many times the action for the current state was tried again, such
+    /// as pressing back on a dialog that doesn't go away.
+    retries: usize,
+    plan: Option<Plan>,
 }
 
 impl Player {
@@ -51,72 +295,190 @@ impl Player {
         Player {
             state: PlayerState::WaitForBoard,
             last_transition,
+            retries: 0,
+            plan: None,
+        }
+    }
+
+    /// Starts over after the device got reconnected.
+    fn restart(&mut self, now: Instant) {
+        self.set_current_state(now, PlayerState::WaitForBoard);
+        self.plan = None;
+    }
+
+    /// Plans pokes that solve `board` from scratch.
+    fn solve(&mut self, board: Board) -> Action {
+        let pokes = board.clone().solve();
+        self.send(Plan {
+            board,
+            pokes,
+            resend: false,
+        })
+    }
+
+    /// After a run of pokes left `observed` instead of a solved board, plans
+    /// to resend only the pokes that got dropped. Falls back to solving from
+    /// scratch if dropped pokes don't explain the board.
+    fn solve_again(&mut self, observed: Board) -> Action {
+        let dropped = self
+            .plan
+            .as_ref()
+            .and_then(|plan| plan.board.dropped_pokes(&plan.pokes, &observed));
+        match dropped {
+            Some(pokes) => self.send(Plan {
+                board: observed,
+                pokes,
+                resend: true,
+            }),
+            None => self.solve(observed),
         }
     }
 
+    fn send(&mut self, plan: Plan) -> Action {
+        let action = Action::Poke(Box::new(plan.pokes.clone()));
+        self.plan = Some(plan);
+        action
+    }
+
     fn set_current_state(&mut self, now: Instant, new_state: PlayerState) {
         self.state = new_state;
         self.last_transition = now;
+        self.retries = 0;
+    }
+
+    /// Stays in the current state, but counts as a transition for timeouts.
+    fn retry(&mut self, now: Instant) {
+        self.last_transition = now;
+        self.retries += 1;
     }
 
-    fn transition(&mut self, ctx: PlayerTransitionContext) -> anyhow::Result<Action> {
-        let PlayerTransitionContext { now, board } = ctx;
+    fn transition(&mut self, ctx: PlayerTransitionContext) -> Result<Action, PlayError> {
+        let PlayerTransitionContext { now, frame, screen } = ctx;
         let elapsed = now
             .checked_duration_since(self.last_transition)
-            .context("`ctx.now` is earlier than `self.last_transition`")?;
-        let maybe_board_state = board.map(|b| {
-            if b.is_solved() {
-                BoardState::Solved
-            } else {
-                BoardState::Unsolved(b)
+            .ok_or(PlayError::ClockWentBackwards)?;
+        let maybe_board_state = match screen {
+            ScreenState::ExpertBoard {
+                board,
+                claim_button,
+            } if board.is_solved() => Some(BoardState::Solved { claim_button }),
+            // No pokes solve what was detected, so it doesn't match the
+            // screen. Tapping a plan for it would scramble the real board.
+            ScreenState::ExpertBoard { board, .. } if !board.is_solvable() => None,
+            ScreenState::ExpertBoard { board, .. } => Some(BoardState::Unsolved(board)),
+            ScreenState::ClaimButton => Some(BoardState::Solved { claim_button: true }),
+            ScreenState::Unknown => None,
+
+            // Dialogs and ads either go away on their own, or after pressing
+            // back. Give them a while first, since pressing back on the puzzle
+            // closes it. The normal puzzle can't be played, so it gets closed
+            // the same way, in the hope that the expert one gets opened.
+            ScreenState::NormalBoard | ScreenState::Popup | ScreenState::Ad => {
+                let patience = if screen == ScreenState::Ad {
+                    Duration::from_secs(60)
+                } else {
+                    Duration::from_secs(2)
+                };
+                if self.state != PlayerState::WaitForBoard {
+                    self.set_current_state(now, PlayerState::WaitForBoard);
+                    return Ok(Action::Wait);
+                }
+                if elapsed <= patience {
+                    return Ok(Action::Wait);
+                }
+                if self.retries >= MAX_GO_BACKS {
+                    return Err(PlayError::Timeout(WaitingFor::Board, elapsed));
+                }
+                self.retry(now);
+                return Ok(Action::GoBack);
             }
-        });
+        };
 
         let action = match (self.state, maybe_board_state) {
+            // A screen that isn't recognized may well be a dialog or an ad
+            // that there's no template for, so press back a few times the
+            // same way before giving up.
             (PlayerState::WaitForBoard, None) => {
-                if elapsed > Duration::from_secs(10) {
-                    bail!("waited for a board for {:?}", elapsed);
+                if elapsed <= Duration::from_secs(10) {
+                    Action::Wait
+                } else if self.retries < MAX_GO_BACKS {
+                    self.retry(now);
+                    Action::GoBack
+                } else {
+                    return Err(PlayError::Timeout(WaitingFor::Board, elapsed));
                 }
-                Action::Wait
             }
             (PlayerState::WaitForBoard, Some(BoardState::Unsolved(b))) => {
                 self.set_current_state(now, PlayerState::WaitForSolvedBoard);
-                Action::Solve(b)
+                self.solve(b)
             }
-            (PlayerState::WaitForBoard, Some(BoardState::Solved)) => {
+            (
+                PlayerState::WaitForBoard | PlayerState::WaitForSolvedBoard,
+                Some(BoardState::Solved { claim_button: true }),
+            ) => {
                 self.set_current_state(now, PlayerState::WaitForUnsolvedBoard);
                 Action::ClaimRewards
             }
 
+            // The board is solved but the claim button hasn't shown up yet.
+            // Tapping now would go into the void. If it doesn't show up at
+            // all, it probably just looks different than the template, so tap
+            // where it should be anyway.
+            (
+                PlayerState::WaitForBoard | PlayerState::WaitForSolvedBoard,
+                Some(BoardState::Solved {
+                    claim_button: false,
+                }),
+            ) => {
+                if elapsed > Duration::from_secs(10) {
+                    self.set_current_state(now, PlayerState::WaitForUnsolvedBoard);
+                    Action::ClaimRewards
+                } else {
+                    Action::Wait
+                }
+            }
+
             (PlayerState::WaitForSolvedBoard | PlayerState::WaitForUnsolvedBoard, None) => {
                 self.set_current_state(now, PlayerState::WaitForBoard);
                 Action::Wait
             }
-            (PlayerState::WaitForSolvedBoard, Some(BoardState::Solved)) => {
-                self.set_current_state(now, PlayerState::WaitForUnsolvedBoard);
-                Action::ClaimRewards
-            }
             (PlayerState::WaitForUnsolvedBoard, Some(BoardState::Unsolved(b))) => {
                 self.set_current_state(now, PlayerState::WaitForSolvedBoard);
-                Action::Solve(b)
+                self.solve(b)
             }
 
             // After solving the board until the screen updates. If the board
-            // doesn't align, it's probably because some clicks didn't register.
-            // Try solving the board again.
+            // still doesn't align in a frame captured well after the taps, it's
+            // probably because some clicks didn't register. Resend the ones
+            // that went missing. Frames captured before that may not show the
+            // taps yet, so they say nothing about whether the clicks
+            // registered. A screen that doesn't change at all sends no fresh
+            // frames, but the device gives up waiting for one within a
+            // second, after which the latest frame is up to date.
             (PlayerState::WaitForSolvedBoard, Some(BoardState::Unsolved(b))) => {
-                if elapsed > Duration::from_secs(1) {
+                let frame_age = frame
+                    .captured_at
+                    .saturating_duration_since(self.last_transition);
+                if frame_age > Duration::from_secs(1) || elapsed > Duration::from_secs(2) {
                     self.set_current_state(now, PlayerState::WaitForSolvedBoard);
-                    Action::Solve(b)
+                    self.solve_again(b)
                 } else {
                     Action::Wait
                 }
             }
 
-            // After hitting the claim button until the screen updates
-            (PlayerState::WaitForUnsolvedBoard, Some(BoardState::Solved)) => {
-                if elapsed > Duration::from_secs(1) {
-                    bail!("waited for unsolved board for {:?}", elapsed);
+            // After hitting the claim button until the screen updates. If the
+            // button is still there, the tap probably didn't register. If it
+            // still doesn't after a few more taps, tapping won't help.
+            (PlayerState::WaitForUnsolvedBoard, Some(BoardState::Solved { claim_button })) => {
+                if claim_button
+                    && elapsed > Duration::from_secs(1)
+                    && self.retries < MAX_CLAIM_RETRIES
+                {
+                    self.retry(now);
+                    Action::ClaimRewards
+                } else if elapsed > Duration::from_secs(5) {
+                    return Err(PlayError::Timeout(WaitingFor::UnsolvedBoard, elapsed));
                 } else {
                     Action::Wait
                 }
@@ -126,30 +488,156 @@ impl Player {
     }
 }
 
-pub fn play<D>(mut device: D) -> anyhow::Result<()>
+/// How many times in a row to press back on a screen that doesn't go away
+/// before giving up, since pressing back once too often leaves the game.
+const MAX_GO_BACKS: usize = 3;
+
+/// How many times to tap the claim button again while it stays visible.
+const MAX_CLAIM_RETRIES: usize = 3;
+
+/// How long to wait for the next frame before acting on the last one again. The
+/// player's timeouts need to be checked even while the screen doesn't change.
+const IDLE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// How many times in a row the device may fail and get reconnected before
+/// `play` gives up. Failed reconnects count too.
+const MAX_CONSECUTIVE_RECONNECTS: usize = 3;
+
+/// How long to wait before reconnecting again when the last reconnect didn't
+/// help. Doubles with every consecutive reconnect.
+const RECONNECT_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Reconnects the device after `err`, and keeps trying with a backoff while
+/// reconnecting fails, until there have been too many reconnects in a row.
+fn reconnect<D, O>(
+    device: &mut D,
+    observer: &mut O,
+    err: PlayError,
+    reconnects: &mut usize,
+) -> Result<(), PlayError>
 where
     D: Device,
+    O: PlayObserver,
 {
-    let mut player = Player::new(Instant::now());
-
+    let mut last = err;
     loop {
-        let board = device.detect_board().context("detect board")?;
-        let action = player
-            .transition(PlayerTransitionContext {
-                now: Instant::now(),
-                board,
-            })
-            .context("player transition")?;
-        match action {
+        if *reconnects >= MAX_CONSECUTIVE_RECONNECTS {
+            return Err(PlayError::GaveUp {
+                reconnects: *reconnects,
+                last: Box::new(last),
+            });
+        }
+        if *reconnects > 0 {
+            sleep(RECONNECT_BACKOFF * (1u32 << (*reconnects - 1)));
+        }
+        *reconnects += 1;
+        observer.on_reconnect(device.now());
+        let source = match device.reconnect() {
+            Ok(()) => return Ok(()),
+            Err(source) => source,
+        };
+        // Keep what went wrong in the first place rather than nesting the
+        // failed reconnects
+        let after = match last {
+            PlayError::Reconnect { after, .. } => after,
+            last => Box::new(last),
+        };
+        last = PlayError::Reconnect { source, after };
+        observer.on_error(device.now(), ErrorSource::Reconnect, &last);
+    }
+}
+
+/// Plays until `stop` is set or the device fails for good, telling `observer`
+/// along the way.
+pub fn play<D, O>(mut device: D, mut observer: O, stop: &AtomicBool) -> Result<(), PlayError>
+where
+    D: Device,
+    O: PlayObserver,
+{
+    let mut player = Player::new(device.now());
+    let mut reconnects = 0;
+
+    while !stop.load(Ordering::Relaxed) {
+        let detection = match device.detect_screen().map_err(PlayError::Detect) {
+            Ok(detection) => {
+                reconnects = 0;
+                detection
+            }
+            Err(err) => {
+                observer.on_error(device.now(), ErrorSource::Detect, &err);
+                reconnect(&mut device, &mut observer, err, &mut reconnects)?;
+                player.restart(device.now());
+                continue;
+            }
+        };
+        observer.on_detection(&detection);
+        let Detection { frame, screen } = detection;
+        if let ScreenState::ExpertBoard { board, .. } = &screen {
+            observer.on_board_detected(&frame, board);
+        }
+
+        let from = player.state;
+        let since = player.last_transition;
+        let now = device.now();
+        let action = player.transition(PlayerTransitionContext { now, frame, screen });
+        let action = match action {
+            Ok(action) => action,
+            Err(err) => {
+                observer.on_error(now, ErrorSource::Player, &err);
+                return Err(err);
+            }
+        };
+        if player.state != from {
+            let elapsed = now.saturating_duration_since(since);
+            observer.on_state_change(now, from, player.state, elapsed);
+        }
+
+        let tapped_at = device.now();
+        let tapped = match action {
             Action::Wait => {
-                sleep(D::wait_duration());
+                if let Err(err) = device
+                    .wait_for_next_frame(&frame, IDLE_TIMEOUT)
+                    .map_err(|err| PlayError::Device("wait for next frame", err))
+                {
+                    observer.on_error(device.now(), ErrorSource::Wait, &err);
+                    reconnect(&mut device, &mut observer, err, &mut reconnects)?;
+                    player.restart(device.now());
+                }
+                continue;
             }
-            Action::Solve(b) => {
-                device.tap_board(b.solve()).context("tap board")?;
+            Action::Poke(pokes) => {
+                if let Some(plan) = &player.plan {
+                    let again = from == PlayerState::WaitForSolvedBoard;
+                    observer.on_solve(now, plan, again);
+                }
+                device
+                    .tap_board(*pokes)
+                    .map_err(|err| PlayError::Device("tap board", err))
             }
             Action::ClaimRewards => {
-                device.tap_claim_button().context("tap claim button")?;
+                let retry = from == PlayerState::WaitForUnsolvedBoard;
+                observer.on_claim(now, retry);
+                device
+                    .tap_claim_button()
+                    .map_err(|err| PlayError::Device("tap claim button", err))
             }
+            Action::GoBack => {
+                observer.on_go_back(now);
+                device
+                    .press_back()
+                    .map_err(|err| PlayError::Device("press back", err))
+            }
+        };
+        let tapped = tapped.and_then(|()| {
+            device
+                .wait_for_frame_after(tapped_at)
+                .map_err(|err| PlayError::Device("wait for frame after tapping", err))
+        });
+        if let Err(err) = tapped {
+            observer.on_error(device.now(), ErrorSource::Tap, &err);
+            reconnect(&mut device, &mut observer, err, &mut reconnects)?;
+            player.restart(device.now());
         }
     }
+    Ok(())
 }
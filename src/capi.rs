@@ -0,0 +1,153 @@
+//! C API for the solver, for calling it from C and C++ tools. The header is
+//! `include/solve_arrow_puzzle.h`, which [`header`] generates.
+//!
+//! Build the library with
+//! `cargo rustc --release --lib --no-default-features --features capi --crate-type cdylib`,
+//! or `--crate-type staticlib`.
+
+use std::{fmt::Write, slice};
+
+use crate::{
+    expert::{Arrow, ArrowFromU8Error, Board},
+    hex::Hex,
+};
+
+/// How many arrows a board has, and how many tap counts a solution has.
+pub const CELLS: usize = Hex::<Arrow>::POSITIONS.len();
+
+/// What a call of the C API did. Mirrors [`ArrowFromU8Error`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok = 0,
+    /// An arrow was 6 or more.
+    ArrowOutOfRange = 1,
+    NullPointer = 2,
+}
+
+impl From<ArrowFromU8Error> for Status {
+    fn from(err: ArrowFromU8Error) -> Status {
+        match err {
+            ArrowFromU8Error::OutOfRange(_) => Status::ArrowOutOfRange,
+        }
+    }
+}
+
+impl Status {
+    const ALL: [(Status, &'static str, &'static str); 3] = [
+        (Status::Ok, "OK", "Solved."),
+        (
+            Status::ArrowOutOfRange,
+            "ARROW_OUT_OF_RANGE",
+            "An arrow was 6 or more. Nothing was written.",
+        ),
+        (
+            Status::NullPointer,
+            "NULL_POINTER",
+            "A pointer was NULL. Nothing was written.",
+        ),
+    ];
+}
+
+fn board_from_arrows(arrows: &[u8]) -> Result<Board, ArrowFromU8Error> {
+    for &a in arrows {
+        Arrow::try_from(a)?;
+    }
+    let mut arrows = arrows.iter();
+    Ok(Board::new(Hex::from_fn(|_, _| {
+        Arrow(*arrows.next().unwrap())
+    })))
+}
+
+/// Solves the board in `arrows` and writes how many times to tap each cell
+/// to `taps`, both in the order of [`Hex::POSITIONS`].
+///
+/// # Safety
+///
+/// `arrows` must point to [`CELLS`] readable bytes and `taps` to [`CELLS`]
+/// writable ones, or be null.
+#[no_mangle]
+pub unsafe extern "C" fn solve_arrow_puzzle_solve(arrows: *const u8, taps: *mut u8) -> Status {
+    if arrows.is_null() || taps.is_null() {
+        return Status::NullPointer;
+    }
+    let arrows = unsafe { slice::from_raw_parts(arrows, CELLS) };
+    let board = match board_from_arrows(arrows) {
+        Ok(board) => board,
+        Err(err) => return err.into(),
+    };
+    let taps = unsafe { slice::from_raw_parts_mut(taps, CELLS) };
+    for ((&n, _), t) in board.solve().enumerate().zip(taps) {
+        *t = n as u8;
+    }
+    Status::Ok
+}
+
+/// The C header for this module.
+pub fn header() -> String {
+    let mut h = String::new();
+    let statuses = Status::ALL
+        .iter()
+        .map(|&(status, name, doc)| {
+            format!(
+                "    /* {} */\n    SOLVE_ARROW_PUZZLE_{} = {},\n",
+                doc, name, status as i32
+            )
+        })
+        .collect::<String>();
+    write!(
+        h,
+        "\
+/* Generated by `solve_arrow_puzzle::capi::header`. Do not edit. */
+
+#ifndef SOLVE_ARROW_PUZZLE_H
+#define SOLVE_ARROW_PUZZLE_H
+
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern \"C\" {{
+#endif
+
+/* How many arrows a board has, and how many tap counts a solution has. */
+#define SOLVE_ARROW_PUZZLE_CELLS {cells}
+
+typedef enum SolveArrowPuzzleStatus {{
+{statuses}}} SolveArrowPuzzleStatus;
+
+/*
+ * Solves the board in `arrows` and writes how many times to tap each cell to
+ * `taps`, both in the order A0, A1, A2, A3, B0, ..., G6 of the cells on
+ *
+ *              A0
+ *           B0    A1
+ *        C0    B1    A2
+ *     D0    C1    B2    A3
+ *        D1    C2    B3
+ *     E1    D2    C3    B4
+ *        E2    D3    C4
+ *     F2    E3    D4    C5
+ *        F3    E4    D5
+ *     G3    F4    E5    D6
+ *        G4    F5    E6
+ *           G5    F6
+ *              G6
+ *
+ * Arrows are the number of clockwise turns from pointing up, within [0, 6).
+ */
+SolveArrowPuzzleStatus solve_arrow_puzzle_solve(
+    const uint8_t arrows[SOLVE_ARROW_PUZZLE_CELLS],
+    uint8_t taps[SOLVE_ARROW_PUZZLE_CELLS]);
+
+#ifdef __cplusplus
+}}
+#endif
+
+#endif /* SOLVE_ARROW_PUZZLE_H */
+",
+        cells = CELLS,
+        statuses = statuses,
+    )
+    .unwrap();
+    h
+}
@@ -1,29 +1,105 @@
 use std::{
+    convert::Infallible,
     env::temp_dir,
     fmt::Debug,
-    fs::{remove_file, write},
-    io::{Read, Write},
-    iter::{once, repeat},
+    fs::{read, remove_file, write},
+    io::{self, Read, Write},
+    iter::{once, repeat_n},
     net::{SocketAddr, TcpListener, TcpStream},
-    path::PathBuf,
-    process::{Child, Command, Stdio},
-    sync::{Arc, Mutex},
-    thread::{self},
-    time::Duration,
+    ops::{Range, RangeInclusive},
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread::{self, sleep},
+    time::{Duration, Instant},
 };
 
-use anyhow::{anyhow, bail, Context};
 use itertools::Itertools;
 use phf::phf_map;
 use rand::random;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
 use crate::{
-    app::Device,
+    app::{DetectError, Detection, Device, DeviceError, Frame, ScreenState},
     expert::{Arrow, Board},
     hex::Hex,
+    session::{bytes_to_hex, read_session, Event, SessionError, SessionRecorder},
 };
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+/// Why a [`Profile`] doesn't fit the screen.
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum LayoutError {
+    #[error(
+        "want a {}x{} screen in {:?} orientation, but got {}x{}",
+        .want.0, .want.1, .orientation, .got.0, .got.1
+    )]
+    Size {
+        orientation: Orientation,
+        want: (usize, usize),
+        got: (usize, usize),
+    },
+    #[error("screen {side} {length} cannot be divided by video size divider {divider}")]
+    Divider {
+        side: &'static str,
+        length: usize,
+        divider: usize,
+    },
+}
+
+/// Says what was being done when an I/O error happened.
+trait IoContext<T> {
+    fn io_context<C>(self, action: C) -> Result<T, DeviceError>
+    where
+        C: Into<String>;
+}
+
+impl<T> IoContext<T> for io::Result<T> {
+    fn io_context<C>(self, action: C) -> Result<T, DeviceError>
+    where
+        C: Into<String>,
+    {
+        self.map_err(|err| DeviceError::Io(action.into(), err))
+    }
+}
+
+fn check_status(command: &'static str, status: ExitStatus) -> Result<(), DeviceError> {
+    if status.success() {
+        Ok(())
+    } else {
+        Err(DeviceError::Command(command, status))
+    }
+}
+
+/// Accepts a connection, or fails with [`io::ErrorKind::TimedOut`] if none
+/// comes within `timeout`.
+fn accept_within(listener: &TcpListener, timeout: Duration) -> io::Result<TcpStream> {
+    let deadline = Instant::now() + timeout;
+    listener.set_nonblocking(true)?;
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false)?;
+                return Ok(stream);
+            }
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                if Instant::now() >= deadline {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("no connection within {:?}", timeout),
+                    ));
+                }
+                sleep(Duration::from_millis(10));
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Vec2 {
     pub x: f64,
     pub y: f64,
@@ -118,43 +194,368 @@ impl Transform {
             .add(self.axis_b.scale(y as f64))
             .add(self.top_arrow)
     }
+
+    fn arrow_tap_positions(&self) -> Hex<(u32, u32)> {
+        Hex::from_fn(|x, y| self.index_to_position(x, y).round_as_u32())
+    }
+
+    fn rotate(&self, orientation: Orientation, screen_width: f64, screen_height: f64) -> Transform {
+        Transform {
+            top_arrow: orientation.rotate_point(self.top_arrow, screen_width, screen_height),
+            arrow_diameter: self.arrow_diameter,
+            axis_a: orientation.rotate_vector(self.axis_a),
+            axis_b: orientation.rotate_vector(self.axis_b),
+        }
+    }
 }
 
-#[derive(Debug)]
-pub struct ScrcpyDevice {
+/// How far the screen the device sends, and takes taps on, is rotated
+/// clockwise from the upright screen that positions like the claim button and
+/// [`Transform`] are measured on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+    ReversePortrait,
+    ReverseLandscape,
+}
+
+impl Orientation {
+    /// Picks the orientation of a `width` by `height` screen, if one isn't
+    /// given. The reverse orientations can't be told apart by the size alone,
+    /// so they have to be given.
+    fn for_size(
+        orientation: Option<Orientation>,
+        upright_width: usize,
+        upright_height: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<Orientation, LayoutError> {
+        let orientation = match orientation {
+            Some(o) => o,
+            None if (width, height) == (upright_width, upright_height) => Orientation::Portrait,
+            None => Orientation::Landscape,
+        };
+        let want = orientation.rotate_size(upright_width, upright_height);
+        if want != (width, height) {
+            return Err(LayoutError::Size {
+                orientation,
+                want,
+                got: (width, height),
+            });
+        }
+        Ok(orientation)
+    }
+
+    fn rotate_size(self, width: usize, height: usize) -> (usize, usize) {
+        match self {
+            Orientation::Portrait | Orientation::ReversePortrait => (width, height),
+            Orientation::Landscape | Orientation::ReverseLandscape => (height, width),
+        }
+    }
+
+    fn rotate_vector(self, v: Vec2) -> Vec2 {
+        match self {
+            Orientation::Portrait => v,
+            Orientation::Landscape => Vec2::new(-v.y, v.x),
+            Orientation::ReversePortrait => Vec2::new(-v.x, -v.y),
+            Orientation::ReverseLandscape => Vec2::new(v.y, -v.x),
+        }
+    }
+
+    fn rotate_point(self, p: Vec2, width: f64, height: f64) -> Vec2 {
+        let offset = match self {
+            Orientation::Portrait => Vec2::new(0.0, 0.0),
+            Orientation::Landscape => Vec2::new(height, 0.0),
+            Orientation::ReversePortrait => Vec2::new(width, height),
+            Orientation::ReverseLandscape => Vec2::new(0.0, width),
+        };
+        self.rotate_vector(p).add(offset)
+    }
+}
+
+/// A rectangle on the upright screen, and the range the mean luma inside it
+/// has to be in.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Region {
+    pub top_left: Vec2,
+    pub bottom_right: Vec2,
+    pub lumas: RangeInclusive<u8>,
+}
+
+impl Region {
+    /// A 20 by 20 patch around `center`.
+    pub fn patch(center: Vec2, lumas: RangeInclusive<u8>) -> Region {
+        let half = Vec2::new(10.0, 10.0);
+        Region {
+            top_left: center.sub(half),
+            bottom_right: center.add(half),
+            lumas,
+        }
+    }
+}
+
+/// The regions that tell the screens apart, other than the expert board which
+/// is told by its arrows. A screen is recognized when all of its regions match,
+/// and never when it has no regions, in which case it shows up as
+/// [`ScreenState::Unknown`].
+///
+/// Templates read from JSON take the default ones for the screens they leave
+/// out, so that ones for popups and ads can be added on their own.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScreenTemplates {
+    pub normal_board: Vec<Region>,
+    /// Also tells whether the claim button is there on the expert board, so
+    /// that it only gets tapped once it's visible.
+    pub claim_button: Vec<Region>,
+    pub popup: Vec<Region>,
+    pub ad: Vec<Region>,
+}
+
+impl Default for ScreenTemplates {
+    /// Measured on `references/` for a 1440x3120 screen. The claim button is
+    /// only in `normal_solved.png`, whose dialog is 99 pixels higher up than
+    /// the expert one. There are no screenshots of other dialogs or of ads,
+    /// so there are no templates for them, and they show up as unknown
+    /// screens.
+    fn default() -> ScreenTemplates {
+        let puzzle_dialog = [
+            (100.0, 600.0),
+            (1300.0, 600.0),
+            (100.0, 2400.0),
+            (1340.0, 2400.0),
+        ]
+        .map(|(x, y)| Region::patch(Vec2::new(x, y), 55..=68));
+        // Next to the arrows, which are the same in every cell
+        let normal_cells = [346.0, 667.0, 990.0, 1313.0]
+            .into_iter()
+            .cartesian_product([1318.0, 1643.0, 1966.0, 2289.0])
+            .map(|(x, y)| Region::patch(Vec2::new(x, y), 36..=42));
+        // Where the "Great! Claim" text is, left of where "I Give Up!" would be.
+        // Reads 127 with the text and 56 without
+        let claim_text = Region {
+            top_left: Vec2::new(400.0, 2745.0),
+            bottom_right: Vec2::new(530.0, 2775.0),
+            lumas: 80..=255,
+        };
+
+        ScreenTemplates {
+            normal_board: puzzle_dialog.iter().cloned().chain(normal_cells).collect(),
+            claim_button: puzzle_dialog.iter().cloned().chain([claim_text]).collect(),
+            popup: vec![],
+            ad: vec![],
+        }
+    }
+}
+
+/// The upright screen, as measured for the device.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub screen_width: usize,
+    pub screen_height: usize,
+    pub claim_button: Vec2,
+    pub transform: Transform,
+    /// Picked from the size of the frames if not given.
+    pub orientation: Option<Orientation>,
+    pub templates: ScreenTemplates,
+}
+
+impl Profile {
+    /// What a frame shows, the way the devices detect it. The frame is
+    /// `frame_width` by `frame_height` lumas, `size_divider` times smaller than
+    /// the screen.
+    pub fn detect_screen(
+        &self,
+        lumas: &[u8],
+        frame_width: usize,
+        frame_height: usize,
+        size_divider: usize,
+    ) -> Result<ScreenState, LayoutError> {
+        let layout = self.layout(frame_width, frame_height, size_divider)?;
+        Ok(layout.detect_screen(lumas))
+    }
+
+    /// `frame_width` and `frame_height` are the size of the frames the device
+    /// sends, which are `size_divider` times smaller than the screen.
+    fn layout(
+        &self,
+        frame_width: usize,
+        frame_height: usize,
+        size_divider: usize,
+    ) -> Result<Layout, LayoutError> {
+        let orientation = Orientation::for_size(
+            self.orientation,
+            self.screen_width / size_divider,
+            self.screen_height / size_divider,
+            frame_width,
+            frame_height,
+        )?;
+        let (screen_width, screen_height) =
+            orientation.rotate_size(self.screen_width, self.screen_height);
+        let (w, h) = (self.screen_width as f64, self.screen_height as f64);
+        let (claim_button_x, claim_button_y) = orientation
+            .rotate_point(self.claim_button, w, h)
+            .round_as_u32();
+        let transform = self.transform.rotate(orientation, w, h);
+        let sample = |regions: &[Region]| {
+            regions
+                .iter()
+                .map(|r| {
+                    SampledRegion::new(
+                        r,
+                        orientation,
+                        w,
+                        h,
+                        frame_width,
+                        frame_height,
+                        size_divider,
+                    )
+                })
+                .collect_vec()
+        };
+        let templates = &self.templates;
+        Ok(Layout {
+            screen_width,
+            screen_height,
+            claim_button_x,
+            claim_button_y,
+            arrow_tap_positions: transform.arrow_tap_positions(),
+            arrow_sampler: ArrowSampler::new(&transform, frame_width, size_divider),
+            claim_button_template: sample(&templates.claim_button),
+            screen_templates: vec![
+                (ScreenState::NormalBoard, sample(&templates.normal_board)),
+                (ScreenState::ClaimButton, sample(&templates.claim_button)),
+                (ScreenState::Popup, sample(&templates.popup)),
+                (ScreenState::Ad, sample(&templates.ad)),
+            ],
+        })
+    }
+}
+
+/// Where to tap and what to sample on the screen in one orientation.
+#[derive(Debug, Clone)]
+struct Layout {
+    /// The size of the screen in this orientation, which taps are relative to.
     screen_width: usize,
     screen_height: usize,
     claim_button_x: u32,
     claim_button_y: u32,
-    video_size_divider: usize,
     arrow_tap_positions: Hex<(u32, u32)>,
-    luma_sample_positions: Hex<Vec<(usize, usize)>>,
-    scrcpy_server_temp_path: PathBuf,
-    video_server: Child,
-    control_server: Child,
-    ffmpeg: Child,
-    control_stream: TcpStream,
-    lumas: Arc<Mutex<Vec<u8>>>,
+    arrow_sampler: ArrowSampler,
+    claim_button_template: Vec<SampledRegion>,
+    /// In the order they're tried.
+    screen_templates: Vec<(ScreenState, Vec<SampledRegion>)>,
 }
 
-impl Drop for ScrcpyDevice {
-    fn drop(&mut self) {
-        let _ = remove_file(&self.scrcpy_server_temp_path);
-        let _ = self.video_server.kill();
-        let _ = self.video_server.wait();
-        let _ = self.control_server.kill();
-        let _ = self.control_server.wait();
-        let _ = self.ffmpeg.kill();
-        let _ = self.ffmpeg.wait();
+impl Layout {
+    fn detect_screen(&self, lumas: &[u8]) -> ScreenState {
+        if let Some(board) = self.arrow_sampler.detect_board(lumas) {
+            return ScreenState::ExpertBoard {
+                board,
+                claim_button: SampledRegion::all_match(&self.claim_button_template, lumas),
+            };
+        }
+        self.screen_templates
+            .iter()
+            .find(|(_, regions)| SampledRegion::all_match(regions, lumas))
+            .map(|(screen, _)| screen.clone())
+            .unwrap_or(ScreenState::Unknown)
     }
 }
 
-impl Device for ScrcpyDevice {
-    fn wait_duration() -> Duration {
-        Duration::from_millis(1)
+/// A [`Region`] on a luma plane.
+#[derive(Debug, Clone)]
+struct SampledRegion {
+    lumas_width: usize,
+    xs: Range<usize>,
+    ys: Range<usize>,
+    lumas: RangeInclusive<u8>,
+}
+
+impl SampledRegion {
+    fn new(
+        region: &Region,
+        orientation: Orientation,
+        screen_width: f64,
+        screen_height: f64,
+        lumas_width: usize,
+        lumas_height: usize,
+        size_divider: usize,
+    ) -> SampledRegion {
+        let [a, b] = [region.top_left, region.bottom_right].map(|p| {
+            orientation
+                .rotate_point(p, screen_width, screen_height)
+                .scale(1.0 / size_divider as f64)
+                .round_as_usize()
+        });
+        // At least one luma, even for tiny regions
+        let span = |a: usize, b: usize, len: usize| {
+            let start = a.min(b).min(len - 1);
+            start..a.max(b).clamp(start + 1, len)
+        };
+        SampledRegion {
+            lumas_width,
+            xs: span(a.0, b.0, lumas_width),
+            ys: span(a.1, b.1, lumas_height),
+            lumas: region.lumas.clone(),
+        }
+    }
+
+    /// Never matches without any regions.
+    fn all_match(regions: &[SampledRegion], lumas: &[u8]) -> bool {
+        !regions.is_empty() && regions.iter().all(|r| r.matches(lumas))
+    }
+
+    fn matches(&self, lumas: &[u8]) -> bool {
+        let sum: usize = self
+            .ys
+            .clone()
+            .flat_map(|y| &lumas[self.lumas_width * y..][self.xs.clone()])
+            .map(|&l| l as usize)
+            .sum();
+        let mean = sum / (self.xs.len() * self.ys.len());
+        self.lumas.contains(&(mean as u8))
+    }
+}
+
+/// Reads the arrows off a luma plane by averaging a ring of samples inside each
+/// arrow.
+#[derive(Debug, Clone)]
+struct ArrowSampler {
+    lumas_width: usize,
+    sample_positions: Hex<Vec<(usize, usize)>>,
+}
+
+impl ArrowSampler {
+    const SAMPLE_COUNT_PER_ARROW: usize = 8;
+
+    /// `size_divider` is how many times smaller the luma plane is than the
+    /// screen.
+    fn new(transform: &Transform, lumas_width: usize, size_divider: usize) -> ArrowSampler {
+        use std::f64::consts::PI;
+
+        let sample_positions = Hex::from_fn(|x, y| {
+            let center = transform.index_to_position(x, y);
+            let diff = Vec2::new(1.0, 0.0).scale(transform.arrow_diameter / 2.0 * 0.75);
+            (0..Self::SAMPLE_COUNT_PER_ARROW)
+                .map(|i| {
+                    let angle = 2.0 * PI * i as f64 / Self::SAMPLE_COUNT_PER_ARROW as f64;
+                    center
+                        .add(diff.rotate(angle))
+                        .scale(1.0 / size_divider as f64)
+                        .round_as_usize()
+                })
+                .collect_vec()
+        });
+        ArrowSampler {
+            lumas_width,
+            sample_positions,
+        }
     }
 
-    fn detect_board(&mut self) -> anyhow::Result<Option<Board>> {
+    fn detect_board(&self, lumas: &[u8]) -> Option<Board> {
         static LUMA_TO_ARROW: phf::Map<u8, Arrow> = phf_map! {
             39u8 => Arrow(0),
             31u8 => Arrow(0),
@@ -165,101 +566,338 @@ impl Device for ScrcpyDevice {
             89u8 => Arrow(5),
         };
 
-        let lumas = self
-            .lumas
-            .lock()
-            .map_err(|err| anyhow!("failed to take the lock for lumas: {}", err))?;
-        let lumas_width = self.screen_width / self.video_size_divider;
-        let arrows = self.luma_sample_positions.try_map_by_ref(|ps| {
+        let arrows = self.sample_positions.try_map_by_ref(|ps| {
             let luma = ps
                 .iter()
-                .map(|&(x, y)| lumas[x + lumas_width * y] as f64)
+                .map(|&(x, y)| lumas[x + self.lumas_width * y] as f64)
                 .sum::<f64>()
                 / Self::SAMPLE_COUNT_PER_ARROW as f64;
             let luma = luma.round() as u8;
-            LUMA_TO_ARROW
-                .get(&luma)
-                .copied()
-                .ok_or_else(|| anyhow!("no arrows correspond to luma value {}", luma))
+            LUMA_TO_ARROW.get(&luma).copied().ok_or(())
         });
-        Ok(arrows.ok().map(Board::new))
+        arrows.ok().map(Board::new)
     }
+}
 
-    fn tap_board(&mut self, taps: Hex<usize>) -> anyhow::Result<()> {
-        let taps = taps
-            .enumerate()
-            .zip(self.arrow_tap_positions.enumerate())
-            .flat_map(|((&n, _), (&(x, y), _))| repeat((x, y)).take(n));
-        let taps = Self::serialize_taps(self.screen_width, self.screen_height, taps);
-        self.control_stream.write_all(&taps).context("tap board")
+#[derive(Debug)]
+struct Video {
+    frame: Frame,
+    lumas: Vec<u8>,
+    /// Why the pipeline stopped producing frames and what the thread was
+    /// doing, set by whichever reader thread ends first. Shared, since every
+    /// call after that fails with it.
+    error: Option<(&'static str, Arc<DeviceError>)>,
+}
+
+impl Video {
+    /// Fails once the pipeline has stopped.
+    fn check_error(&self) -> Result<(), DeviceError> {
+        match &self.error {
+            Some((action, source)) => Err(DeviceError::VideoStopped {
+                action,
+                source: source.clone(),
+            }),
+            None => Ok(()),
+        }
     }
+}
 
-    fn tap_claim_button(&mut self) -> anyhow::Result<()> {
-        let taps = Self::serialize_taps(
-            self.screen_width,
-            self.screen_height,
-            once((self.claim_button_x, self.claim_button_y)),
-        );
-        self.control_stream
-            .write_all(&taps)
-            .context("tap claim button")
+/// The latest frame, shared between the threads that decode the video and the
+/// device, which can block until the next one arrives.
+#[derive(Debug)]
+struct SharedVideo {
+    video: Mutex<Video>,
+    /// Notified on every new frame and when the pipeline fails.
+    changed: Condvar,
+}
+
+impl SharedVideo {
+    fn new(video: Video) -> SharedVideo {
+        SharedVideo {
+            video: Mutex::new(video),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Video>, DeviceError> {
+        self.video
+            .lock()
+            .map_err(|_| DeviceError::Poisoned("lumas"))
+    }
+
+    /// Replaces the lumas with the next frame's and wakes up the waiters.
+    fn publish<F>(&self, write_lumas: F) -> Result<(), DeviceError>
+    where
+        F: FnOnce(&mut [u8]),
+    {
+        let mut video = self.lock()?;
+        video.frame = Frame {
+            sequence: video.frame.sequence + 1,
+            captured_at: Instant::now(),
+        };
+        write_lumas(&mut video.lumas);
+        drop(video);
+        self.changed.notify_all();
+        Ok(())
+    }
+
+    /// `action` says what the thread was doing.
+    fn fail(&self, action: &'static str, err: DeviceError) {
+        let mut video = self.video.lock().unwrap_or_else(|err| err.into_inner());
+        if video.error.is_none() {
+            video.error = Some((action, Arc::new(err)));
+        }
+        drop(video);
+        self.changed.notify_all();
+    }
+
+    /// Blocks until `done` holds for the latest frame or `deadline` passes,
+    /// and returns the latest frame either way. Fails once the pipeline has
+    /// stopped.
+    fn wait_until<F>(&self, deadline: Instant, mut done: F) -> Result<Frame, DeviceError>
+    where
+        F: FnMut(&Frame) -> bool,
+    {
+        let mut video = self.lock()?;
+        loop {
+            video.check_error()?;
+            let now = Instant::now();
+            if done(&video.frame) || now >= deadline {
+                return Ok(video.frame);
+            }
+            video = self
+                .changed
+                .wait_timeout(video, deadline - now)
+                .map_err(|_| DeviceError::Poisoned("lumas"))?
+                .0;
+        }
     }
 }
 
-impl ScrcpyDevice {
-    const SAMPLE_COUNT_PER_ARROW: usize = 8;
+/// How the H.264 video stream from the scrcpy server gets decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VideoBackend {
+    /// Pipe the stream through an `ffmpeg` child process.
+    #[cfg_attr(not(feature = "libavcodec"), default)]
+    Ffmpeg,
+    /// Decode the stream in-process with libavcodec.
+    #[cfg(feature = "libavcodec")]
+    #[default]
+    Libavcodec,
+}
 
-    pub fn new(
-        screen_width: usize,
-        screen_height: usize,
-        claim_button: Vec2,
-        video_size_divider: usize,
-        transform: Transform,
-        scrcpy_video_port: u16,
-        scrcpy_control_port: u16,
-    ) -> anyhow::Result<ScrcpyDevice> {
-        use std::f64::consts::PI;
+/// Everything that has to be torn down and started again when the connection
+/// to the device breaks: the two scrcpy servers, the decoder and the threads
+/// that shovel the video between them.
+#[derive(Debug)]
+struct Pipeline {
+    scrcpy_server_temp_path: PathBuf,
+    video_server: Child,
+    control_server: Child,
+    /// Only there with [`VideoBackend::Ffmpeg`].
+    ffmpeg: Option<Child>,
+    control_stream: TcpStream,
+    video_width: usize,
+    video_height: usize,
+    video: Arc<SharedVideo>,
+}
 
-        if screen_width % video_size_divider != 0 {
-            bail!(
-                "screen width {} cannot be divided by video size divider {}",
-                screen_width,
-                video_size_divider
-            );
+/// Set in the frame meta of the packets with the SPS and PPS.
+/// https://github.com/Genymobile/scrcpy/blob/v2.4/doc/develop.md#video-and-audio
+const PACKET_FLAG_CONFIG: u64 = 1 << 63;
+
+/// The size of the video in the H.264 sequence parameter set of a config
+/// packet, after cropping.
+/// https://www.itu.int/rec/T-REC-H.264 7.3.2.1.1
+fn sps_size(config: &[u8]) -> Option<(usize, usize)> {
+    /// Reads the RBSP of a NAL unit bit by bit, skipping the emulation
+    /// prevention bytes.
+    struct Bits<'a> {
+        bytes: &'a [u8],
+        zeros: usize,
+        byte: u8,
+        bits_left: u32,
+    }
+
+    impl Bits<'_> {
+        fn bit(&mut self) -> Option<u32> {
+            if self.bits_left == 0 {
+                let (&byte, rest) = self.bytes.split_first()?;
+                self.bytes = rest;
+                if self.zeros >= 2 && byte == 3 {
+                    self.zeros = 0;
+                    return self.bit();
+                }
+                self.zeros = if byte == 0 { self.zeros + 1 } else { 0 };
+                self.byte = byte;
+                self.bits_left = 8;
+            }
+            self.bits_left -= 1;
+            Some(u32::from(self.byte >> self.bits_left) & 1)
         }
-        if screen_height % video_size_divider != 0 {
-            bail!(
-                "screen height {} cannot be divided by video size divider {}",
-                screen_height,
-                video_size_divider
-            );
+
+        fn bits(&mut self, n: u32) -> Option<u32> {
+            (0..n).try_fold(0, |value, _| Some(value << 1 | self.bit()?))
         }
-        let video_width = screen_width / video_size_divider;
-        let video_height = screen_height / video_size_divider;
 
-        let (claim_button_x, claim_button_y) = claim_button.round_as_u32();
-        let arrow_tap_positions =
-            Hex::from_fn(|x, y| transform.index_to_position(x, y).round_as_u32());
-        let luma_sample_positions = Hex::from_fn(|x, y| {
-            let center = transform.index_to_position(x, y);
-            let diff = Vec2::new(1.0, 0.0).scale(transform.arrow_diameter / 2.0 * 0.75);
-            (0..Self::SAMPLE_COUNT_PER_ARROW)
-                .map(|i| {
-                    let angle = 2.0 * PI * i as f64 / Self::SAMPLE_COUNT_PER_ARROW as f64;
-                    center
-                        .add(diff.rotate(angle))
-                        .scale(1.0 / video_size_divider as f64)
-                        .round_as_usize()
-                })
-                .collect_vec()
-        });
+        /// An unsigned Exp-Golomb code.
+        fn ue(&mut self) -> Option<u32> {
+            let mut leading_zeros = 0;
+            while self.bit()? == 0 {
+                leading_zeros += 1;
+                if leading_zeros > 31 {
+                    return None;
+                }
+            }
+            Some((1 << leading_zeros) - 1 + self.bits(leading_zeros)?)
+        }
+
+        /// A signed Exp-Golomb code.
+        fn se(&mut self) -> Option<i32> {
+            let code = self.ue()?;
+            let value = code.div_ceil(2) as i32;
+            Some(if code % 2 == 0 { -value } else { value })
+        }
+    }
+
+    // NAL units in the Annex B byte stream start after a 00 00 01 start code
+    let sps = config
+        .windows(3)
+        .enumerate()
+        .filter(|(_, start_code)| *start_code == [0, 0, 1])
+        .map(|(i, _)| &config[i + 3..])
+        .find(|nal| nal.first().is_some_and(|header| header & 0x1f == 7))?;
+    let mut bits = Bits {
+        bytes: &sps[1..],
+        zeros: 0,
+        byte: 0,
+        bits_left: 0,
+    };
+
+    let profile_idc = bits.bits(8)?;
+    bits.bits(16)?; // constraint_set_flags, level_idc
+    bits.ue()?; // seq_parameter_set_id
+    let mut chroma_format_idc = 1;
+    let mut separate_colour_plane = false;
+    if [100, 110, 122, 244, 44, 83, 86, 118, 128, 138, 139, 134, 135].contains(&profile_idc) {
+        chroma_format_idc = bits.ue()?;
+        if chroma_format_idc == 3 {
+            separate_colour_plane = bits.bit()? == 1;
+        }
+        bits.ue()?; // bit_depth_luma_minus8
+        bits.ue()?; // bit_depth_chroma_minus8
+        bits.bit()?; // qpprime_y_zero_transform_bypass_flag
+        if bits.bit()? == 1 {
+            // seq_scaling_list_present_flags
+            for i in 0..if chroma_format_idc == 3 { 12 } else { 8 } {
+                if bits.bit()? == 1 {
+                    let (mut last_scale, mut next_scale) = (8, 8);
+                    for _ in 0..if i < 6 { 16 } else { 64 } {
+                        if next_scale != 0 {
+                            next_scale = (last_scale + bits.se()? + 256) % 256;
+                        }
+                        if next_scale != 0 {
+                            last_scale = next_scale;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    bits.ue()?; // log2_max_frame_num_minus4
+    match bits.ue()? {
+        0 => {
+            bits.ue()?; // log2_max_pic_order_cnt_lsb_minus4
+        }
+        1 => {
+            bits.bit()?; // delta_pic_order_always_zero_flag
+            bits.se()?; // offset_for_non_ref_pic
+            bits.se()?; // offset_for_top_to_bottom_field
+            for _ in 0..bits.ue()? {
+                bits.se()?; // offset_for_ref_frame
+            }
+        }
+        _ => {}
+    }
+    bits.ue()?; // max_num_ref_frames
+    bits.bit()?; // gaps_in_frame_num_value_allowed_flag
+    let width_in_mbs = bits.ue()? as usize + 1;
+    let height_in_map_units = bits.ue()? as usize + 1;
+    let frame_mbs_only = bits.bit()? as usize;
+    if frame_mbs_only == 0 {
+        bits.bit()?; // mb_adaptive_frame_field_flag
+    }
+    bits.bit()?; // direct_8x8_inference_flag
+    let [left, right, top, bottom] = if bits.bit()? == 1 {
+        [bits.ue()?, bits.ue()?, bits.ue()?, bits.ue()?].map(|offset| offset as usize)
+    } else {
+        [0; 4]
+    };
 
+    let (crop_unit_x, crop_unit_y) = match (separate_colour_plane, chroma_format_idc) {
+        (true, _) | (_, 0) => (1, 2 - frame_mbs_only),
+        (_, 1) => (2, 2 * (2 - frame_mbs_only)),
+        (_, 2) => (2, 2 - frame_mbs_only),
+        _ => (1, 2 - frame_mbs_only),
+    };
+    let width = (16 * width_in_mbs).checked_sub(crop_unit_x * (left + right))?;
+    let height = (16 * height_in_map_units * (2 - frame_mbs_only))
+        .checked_sub(crop_unit_y * (top + bottom))?;
+    Some((width, height))
+}
+
+/// What [`Pipeline::start`] has set up so far, torn down again if starting
+/// fails halfway. The [`Pipeline`] takes it over once it's built.
+#[derive(Debug)]
+struct Started {
+    scrcpy_server_temp_path: Option<PathBuf>,
+    video_server: Option<Child>,
+    control_server: Option<Child>,
+}
+
+impl Drop for Started {
+    fn drop(&mut self) {
+        if let Some(path) = &self.scrcpy_server_temp_path {
+            let _ = remove_file(path);
+        }
+        for server in [&mut self.video_server, &mut self.control_server]
+            .into_iter()
+            .flatten()
+        {
+            let _ = server.kill();
+            let _ = server.wait();
+        }
+    }
+}
+
+impl Drop for Pipeline {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl Pipeline {
+    /// How long the scrcpy servers get to connect, which they never do if
+    /// they failed to start.
+    const ACCEPT_TIMEOUT: Duration = Duration::from_secs(10);
+
+    fn start(
+        backend: VideoBackend,
+        max_video_size: usize,
+        scrcpy_video_port: u16,
+        scrcpy_control_port: u16,
+    ) -> Result<Pipeline, DeviceError> {
         let scrcpy_server_temp_path = temp_dir().join(random::<u64>().to_string());
+        let mut started = Started {
+            scrcpy_server_temp_path: Some(scrcpy_server_temp_path.clone()),
+            video_server: None,
+            control_server: None,
+        };
         write(
             &scrcpy_server_temp_path,
             include_bytes!("../scrcpy-server-v2.4"),
         )
-        .context("write scrcpy server to temp file")?;
+        .io_context("write scrcpy server to temp file")?;
 
         let adb_push_status = Command::new("adb")
             .args([
@@ -272,10 +910,8 @@ impl ScrcpyDevice {
             .stderr(Stdio::null())
             .spawn()
             .and_then(|mut c| c.wait())
-            .context("push scrcpy server to android device")?;
-        if !adb_push_status.success() {
-            bail!("adb push failed: {}", adb_push_status);
-        }
+            .io_context("push scrcpy server to android device")?;
+        check_status("adb push", adb_push_status)?;
 
         let video_scid = random::<u32>() & 0x7fffffff;
         let control_scid = random::<u32>() & 0x7fffffff;
@@ -290,7 +926,7 @@ impl ScrcpyDevice {
             .stderr(Stdio::null())
             .spawn()
             .and_then(|mut c| c.wait())
-            .context("create reverse tcp tunnel for video stream")?;
+            .io_context("create reverse tcp tunnel for video stream")?;
         let adb_reverse_control_status = Command::new("adb")
             .args([
                 "reverse",
@@ -302,26 +938,16 @@ impl ScrcpyDevice {
             .stderr(Stdio::null())
             .spawn()
             .and_then(|mut c| c.wait())
-            .context("create reverse tcp tunnel for control stream")?;
-        if !adb_reverse_video_status.success() {
-            bail!(
-                "adb reverse for video stream failed: {}",
-                adb_reverse_video_status
-            );
-        }
-        if !adb_reverse_control_status.success() {
-            bail!(
-                "adb reverse for control stream failed: {}",
-                adb_reverse_control_status
-            );
-        }
+            .io_context("create reverse tcp tunnel for control stream")?;
+        check_status("adb reverse for video stream", adb_reverse_video_status)?;
+        check_status("adb reverse for control stream", adb_reverse_control_status)?;
 
         let video_tcp_listener =
             TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], scrcpy_video_port)))
-                .context("listen to tcp connection for video stream")?;
+                .io_context("listen to tcp connection for video stream")?;
         let control_tcp_listener =
             TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], scrcpy_control_port)))
-                .context("listen to tcp connection for control stream")?;
+                .io_context("listen to tcp connection for control stream")?;
         let video_server = Command::new("adb")
             .args([
                 "shell",
@@ -334,15 +960,19 @@ impl ScrcpyDevice {
                 "video=true",
                 "audio=false",
                 "control=false",
-                "raw_stream=true",
-                &format!("max_size={}", video_width.max(video_height)),
+                "send_device_meta=false",
+                "send_dummy_byte=false",
+                "send_codec_meta=true",
+                "send_frame_meta=true",
+                &format!("max_size={}", max_video_size),
                 "video_bit_rate=67108864", // 64 * 1024 * 1024
             ])
             .stdin(Stdio::null())
             .stdout(Stdio::null())
             .stderr(Stdio::null())
             .spawn()
-            .context("start video server")?;
+            .io_context("start video server")?;
+        started.video_server = Some(video_server);
         let control_server = Command::new("adb")
             .args([
                 "shell",
@@ -361,13 +991,12 @@ impl ScrcpyDevice {
             .stdout(Stdio::null())
             .stderr(Stdio::null())
             .spawn()
-            .context("start control server")?;
-        let (mut video_stream, _) = video_tcp_listener
-            .accept()
-            .context("accept tcp connection for video stream")?;
-        let (control_stream, _) = control_tcp_listener
-            .accept()
-            .context("accept tcp connection for control stream")?;
+            .io_context("start control server")?;
+        started.control_server = Some(control_server);
+        let mut video_stream = accept_within(&video_tcp_listener, Self::ACCEPT_TIMEOUT)
+            .io_context("accept tcp connection for video stream")?;
+        let control_stream = accept_within(&control_tcp_listener, Self::ACCEPT_TIMEOUT)
+            .io_context("accept tcp connection for control stream")?;
 
         let adb_reverse_remove_status = Command::new("adb")
             .args(["reverse", "--remove-all"])
@@ -376,14 +1005,64 @@ impl ScrcpyDevice {
             .stderr(Stdio::null())
             .spawn()
             .and_then(|mut c| c.wait())
-            .context("remove all reverse tcp tunnels")?;
-        if !adb_reverse_remove_status.success() {
-            bail!(
-                "failed to remove all reverse tcp tunnels: {}",
-                adb_reverse_remove_status
-            );
-        }
+            .io_context("remove all reverse tcp tunnels")?;
+        check_status("adb reverse --remove-all", adb_reverse_remove_status)?;
+
+        // The codec id followed by the size of the video, which tells how the
+        // screen is rotated
+        // https://github.com/Genymobile/scrcpy/blob/v2.4/doc/develop.md#video-and-audio
+        let mut codec_meta = [0u8; 12];
+        video_stream
+            .read_exact(&mut codec_meta)
+            .io_context("read codec meta")?;
+        let video_width = u32::from_be_bytes(codec_meta[4..8].try_into().unwrap()) as usize;
+        let video_height = u32::from_be_bytes(codec_meta[8..12].try_into().unwrap()) as usize;
+
+        let video = Arc::new(SharedVideo::new(Video {
+            frame: Frame {
+                sequence: 0,
+                captured_at: Instant::now(),
+            },
+            lumas: vec![0u8; video_width * video_height],
+            error: None,
+        }));
+        let ffmpeg = match backend {
+            VideoBackend::Ffmpeg => Some(Self::start_ffmpeg(
+                video_stream,
+                &video,
+                video_width,
+                video_height,
+            )?),
+            #[cfg(feature = "libavcodec")]
+            VideoBackend::Libavcodec => {
+                let video = video.clone();
+                thread::spawn(move || {
+                    let Err(err) =
+                        Self::decode_video_stream(video_stream, &video, video_width, video_height);
+                    video.fail("decode video stream", err);
+                });
+                None
+            }
+        };
+
+        Ok(Pipeline {
+            scrcpy_server_temp_path: started.scrcpy_server_temp_path.take().unwrap(),
+            video_server: started.video_server.take().unwrap(),
+            control_server: started.control_server.take().unwrap(),
+            ffmpeg,
+            control_stream,
+            video_width,
+            video_height,
+            video,
+        })
+    }
 
+    fn start_ffmpeg(
+        video_stream: TcpStream,
+        video: &Arc<SharedVideo>,
+        video_width: usize,
+        video_height: usize,
+    ) -> Result<Child, DeviceError> {
         let mut ffmpeg = Command::new("ffmpeg")
             .args(["-re"])
             .args(["-flags", "low_delay"])
@@ -397,49 +1076,382 @@ impl ScrcpyDevice {
             .stdout(Stdio::piped())
             .stderr(Stdio::null())
             .spawn()
-            .context("spawn ffmpeg")?;
-        let mut ffmpeg_stdin = ffmpeg.stdin.take().context("take ffmpeg stdin")?;
-        let mut ffmpeg_stdout = ffmpeg.stdout.take().context("take ffmpeg stdout")?;
+            .io_context("start ffmpeg")?;
+        let ffmpeg_stdin = ffmpeg.stdin.take().expect("ffmpeg stdin is piped");
+        let ffmpeg_stdout = ffmpeg.stdout.take().expect("ffmpeg stdout is piped");
 
-        thread::spawn(move || {
-            let mut buf = vec![0u8; 1 << 20];
-            loop {
-                let read_size = video_stream.read(&mut buf).unwrap();
-                ffmpeg_stdin.write_all(&buf[0..read_size]).unwrap();
+        {
+            let video = video.clone();
+            thread::spawn(move || {
+                let Err(err) = Self::forward_video_stream(video_stream, ffmpeg_stdin);
+                video.fail("forward video stream to ffmpeg", err);
+            });
+        }
+        {
+            let video = video.clone();
+            thread::spawn(move || {
+                let Err(err) = Self::read_lumas(ffmpeg_stdout, &video, video_width, video_height);
+                video.fail("read decoded frames from ffmpeg", err);
+            });
+        }
+        Ok(ffmpeg)
+    }
+
+    /// Strips the frame meta off the packets before passing them on to
+    /// ffmpeg.
+    ///
+    /// ffmpeg scales the frames back to the first size when the video size
+    /// changes, so this has to catch the screen rotating itself. The encoder
+    /// restarts then, and sends new config packets with the new size in the
+    /// SPS.
+    fn forward_video_stream<R, W>(
+        mut video_stream: R,
+        mut ffmpeg_stdin: W,
+    ) -> Result<Infallible, DeviceError>
+    where
+        R: Read,
+        W: Write,
+    {
+        let mut header = [0u8; 12];
+        let mut packet = vec![];
+        let mut video_size = None;
+        loop {
+            video_stream
+                .read_exact(&mut header)
+                .io_context("read packet header")?;
+            let pts_and_flags = u64::from_be_bytes(header[0..8].try_into().unwrap());
+            let packet_size = u32::from_be_bytes(header[8..12].try_into().unwrap());
+            packet.resize(packet_size as usize, 0);
+            video_stream
+                .read_exact(&mut packet)
+                .io_context("read packet")?;
+
+            if pts_and_flags & PACKET_FLAG_CONFIG != 0 {
+                let size = sps_size(&packet).ok_or_else(|| {
+                    DeviceError::Unexpected("config packet without a readable SPS".into())
+                })?;
+                match video_size {
+                    None => video_size = Some(size),
+                    Some(video_size) if video_size != size => {
+                        return Err(DeviceError::Unexpected(format!(
+                            "want {}x{} video, but the encoder restarted with {}x{}",
+                            video_size.0, video_size.1, size.0, size.1
+                        )));
+                    }
+                    Some(_) => {}
+                }
             }
-        });
+            ffmpeg_stdin
+                .write_all(&packet)
+                .io_context("write to ffmpeg stdin")?;
+        }
+    }
 
+    fn read_lumas<R>(
+        mut ffmpeg_stdout: R,
+        video: &SharedVideo,
+        video_width: usize,
+        video_height: usize,
+    ) -> Result<Infallible, DeviceError>
+    where
+        R: Read,
+    {
         let lumas_len = video_width * video_height;
-        let lumas = Arc::new(Mutex::new(vec![0u8; lumas_len]));
+        let yuvs_len = 3 * lumas_len / 2;
+        let mut yuvs = vec![0u8; yuvs_len];
+        loop {
+            ffmpeg_stdout
+                .read_exact(&mut yuvs)
+                .io_context("read frame from ffmpeg stdout")?;
+            video.publish(|lumas| lumas.copy_from_slice(&yuvs[0..lumas_len]))?;
+        }
+    }
+
+    /// Decodes a video stream sent with frame meta, where every packet is
+    /// preceded by a 12 byte header.
+    /// https://github.com/Genymobile/scrcpy/blob/v2.4/doc/develop.md#video-and-audio
+    #[cfg(feature = "libavcodec")]
+    fn decode_video_stream<R>(
+        mut video_stream: R,
+        video: &SharedVideo,
+        video_width: usize,
+        video_height: usize,
+    ) -> Result<Infallible, DeviceError>
+    where
+        R: Read,
+    {
+        use ffmpeg_next::{codec, decoder, error::EAGAIN, frame, Error, Packet};
+
+        fn libavcodec_context(action: &'static str) -> impl Fn(Error) -> DeviceError {
+            move |err| DeviceError::Other(action.into(), Box::new(err))
+        }
+
+        ffmpeg_next::init().map_err(libavcodec_context("initialize libavcodec"))?;
+        let h264 = decoder::find(codec::Id::H264)
+            .ok_or_else(|| DeviceError::Unexpected("libavcodec has no h264 decoder".into()))?;
+        let mut context = codec::Context::new_with_codec(h264);
+        context.set_flags(codec::Flags::LOW_DELAY);
+        let mut decoder = context
+            .decoder()
+            .video()
+            .map_err(libavcodec_context("open h264 decoder"))?;
+        let mut decoded = frame::Video::empty();
+
+        let mut header = [0u8; 12];
+        let mut config = vec![];
+        loop {
+            video_stream
+                .read_exact(&mut header)
+                .io_context("read packet header")?;
+            let pts_and_flags = u64::from_be_bytes(header[0..8].try_into().unwrap());
+            let packet_size = u32::from_be_bytes(header[8..12].try_into().unwrap());
+            let mut packet = vec![0u8; packet_size as usize];
+            video_stream
+                .read_exact(&mut packet)
+                .io_context("read packet")?;
+
+            // Config packets (SPS and PPS) are prepended to the next packet, the
+            // same way scrcpy does
+            if pts_and_flags & PACKET_FLAG_CONFIG != 0 {
+                config = packet;
+                continue;
+            }
+            if !config.is_empty() {
+                config.append(&mut packet);
+                packet = std::mem::take(&mut config);
+            }
+
+            decoder
+                .send_packet(&Packet::copy(&packet))
+                .map_err(libavcodec_context("send packet to decoder"))?;
+            loop {
+                match decoder.receive_frame(&mut decoded) {
+                    Ok(()) => {}
+                    Err(Error::Other { errno: EAGAIN }) => break,
+                    Err(err) => return Err(libavcodec_context("receive frame from decoder")(err)),
+                }
+                let (width, height) = (decoded.width() as usize, decoded.height() as usize);
+                if (width, height) != (video_width, video_height) {
+                    return Err(DeviceError::Unexpected(format!(
+                        "want {}x{} frames, but got {}x{}",
+                        video_width, video_height, width, height
+                    )));
+                }
+
+                let stride = decoded.stride(0);
+                let plane = decoded.data(0);
+                video.publish(|lumas| {
+                    for (y, row) in lumas.chunks_exact_mut(video_width).enumerate() {
+                        row.copy_from_slice(&plane[stride * y..stride * y + video_width]);
+                    }
+                })?;
+            }
+        }
+    }
+
+    /// Fails if any part of the pipeline has stopped, in which case it has to
+    /// be restarted.
+    fn check_alive(&mut self) -> Result<(), DeviceError> {
+        for (name, child) in [
+            ("video server", Some(&mut self.video_server)),
+            ("control server", Some(&mut self.control_server)),
+            ("ffmpeg", self.ffmpeg.as_mut()),
+        ]
+        .into_iter()
+        .filter_map(|(name, child)| Some((name, child?)))
         {
-            let lumas = lumas.clone();
-            thread::spawn(move || {
-                let yuvs_len = 3 * video_width * video_height / 2;
-                let mut yuvs = vec![0u8; yuvs_len];
-                loop {
-                    ffmpeg_stdout.read_exact(&mut yuvs).unwrap();
-                    lumas.lock().unwrap().clone_from_slice(&yuvs[0..lumas_len]);
+            if let Some(status) = child.try_wait().io_context("check child process")? {
+                return Err(DeviceError::Exited(name, status));
+            }
+        }
+        self.video.lock()?.check_error()
+    }
+
+    fn stop(&mut self) {
+        let _ = remove_file(&self.scrcpy_server_temp_path);
+        let _ = self.video_server.kill();
+        let _ = self.video_server.wait();
+        let _ = self.control_server.kill();
+        let _ = self.control_server.wait();
+        if let Some(ffmpeg) = &mut self.ffmpeg {
+            let _ = ffmpeg.kill();
+            let _ = ffmpeg.wait();
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ScrcpyDevice {
+    profile: Profile,
+    video_size_divider: usize,
+    video_backend: VideoBackend,
+    scrcpy_video_port: u16,
+    scrcpy_control_port: u16,
+    layout: Layout,
+    pipeline: Pipeline,
+    recorder: Option<SessionRecorder>,
+}
+
+impl Device for ScrcpyDevice {
+    fn detect_screen(&mut self) -> Result<Detection, DetectError> {
+        self.pipeline.check_alive()?;
+        let video = self.pipeline.video.lock()?;
+        let detection = Detection {
+            frame: video.frame,
+            screen: self.layout.detect_screen(&video.lumas),
+        };
+        if let Some(recorder) = &self.recorder {
+            // Writing the frame takes a while, which the decoder shouldn't
+            // have to wait for
+            let lumas = recorder
+                .wants_lumas(&detection.frame)
+                .then(|| video.lumas.clone());
+            drop(video);
+            recorder.record_frame(
+                &detection.frame,
+                self.pipeline.video_width,
+                self.pipeline.video_height,
+                self.video_size_divider,
+                lumas.as_deref(),
+            );
+        }
+        Ok(detection)
+    }
+
+    fn wait_for_frame_after(&mut self, instant: Instant) -> Result<Frame, DeviceError> {
+        // The video stream only carries a frame when the screen changes
+        const TIMEOUT: Duration = Duration::from_millis(500);
+
+        self.pipeline.check_alive()?;
+        self.pipeline
+            .video
+            .wait_until(instant + TIMEOUT, |frame| frame.captured_at > instant)
+    }
+
+    fn wait_for_next_frame(&mut self, frame: &Frame, timeout: Duration) -> Result<(), DeviceError> {
+        self.pipeline.check_alive()?;
+        let sequence = frame.sequence;
+        self.pipeline
+            .video
+            .wait_until(Instant::now() + timeout, |frame| frame.sequence > sequence)?;
+        Ok(())
+    }
+
+    fn tap_board(&mut self, taps: Hex<usize>) -> Result<(), DeviceError> {
+        let taps = taps
+            .enumerate()
+            .zip(self.layout.arrow_tap_positions.enumerate())
+            .flat_map(|((&n, _), (&(x, y), _))| repeat_n((x, y), n));
+        let taps = Self::serialize_taps(self.layout.screen_width, self.layout.screen_height, taps);
+        self.send_control(&taps)
+            .io_context("send taps to control server")
+    }
+
+    fn tap_claim_button(&mut self) -> Result<(), DeviceError> {
+        let taps = Self::serialize_taps(
+            self.layout.screen_width,
+            self.layout.screen_height,
+            once((self.layout.claim_button_x, self.layout.claim_button_y)),
+        );
+        self.send_control(&taps)
+            .io_context("send tap to control server")
+    }
+
+    fn press_back(&mut self) -> Result<(), DeviceError> {
+        self.send_control(&Self::PRESS_BACK)
+            .io_context("send back key to control server")
+    }
+
+    fn reconnect(&mut self) -> Result<(), DeviceError> {
+        self.pipeline.stop();
+        self.pipeline = Pipeline::start(
+            self.video_backend,
+            self.profile.screen_width.max(self.profile.screen_height) / self.video_size_divider,
+            self.scrcpy_video_port,
+            self.scrcpy_control_port,
+        )?;
+        // The device may have been rotated in the meantime
+        self.layout = self.profile.layout(
+            self.pipeline.video_width,
+            self.pipeline.video_height,
+            self.video_size_divider,
+        )?;
+        Ok(())
+    }
+}
+
+impl ScrcpyDevice {
+    /// https://github.com/Genymobile/scrcpy/blob/v2.4/app/src/control_msg.c
+    const PRESS_BACK: [u8; 4] = {
+        const SC_CONTROL_MSG_TYPE_BACK_OR_SCREEN_ON: u8 = 4;
+        const AKEY_EVENT_ACTION_DOWN: u8 = 0;
+        const AKEY_EVENT_ACTION_UP: u8 = 1;
+
+        [
+            SC_CONTROL_MSG_TYPE_BACK_OR_SCREEN_ON,
+            AKEY_EVENT_ACTION_DOWN,
+            SC_CONTROL_MSG_TYPE_BACK_OR_SCREEN_ON,
+            AKEY_EVENT_ACTION_UP,
+        ]
+    };
+
+    pub fn new(
+        profile: Profile,
+        video_size_divider: usize,
+        video_backend: VideoBackend,
+        scrcpy_video_port: u16,
+        scrcpy_control_port: u16,
+    ) -> Result<ScrcpyDevice, DeviceError> {
+        for (side, length) in [
+            ("width", profile.screen_width),
+            ("height", profile.screen_height),
+        ] {
+            if !length.is_multiple_of(video_size_divider) {
+                return Err(LayoutError::Divider {
+                    side,
+                    length,
+                    divider: video_size_divider,
                 }
-            });
+                .into());
+            }
         }
 
+        let pipeline = Pipeline::start(
+            video_backend,
+            profile.screen_width.max(profile.screen_height) / video_size_divider,
+            scrcpy_video_port,
+            scrcpy_control_port,
+        )?;
+        let layout = profile.layout(
+            pipeline.video_width,
+            pipeline.video_height,
+            video_size_divider,
+        )?;
+
         Ok(ScrcpyDevice {
-            screen_width,
-            screen_height,
-            claim_button_x,
-            claim_button_y,
+            profile,
             video_size_divider,
-            arrow_tap_positions,
-            luma_sample_positions,
-            scrcpy_server_temp_path,
-            video_server,
-            control_server,
-            ffmpeg,
-            control_stream,
-            lumas,
+            video_backend,
+            scrcpy_video_port,
+            scrcpy_control_port,
+            layout,
+            pipeline,
+            recorder: None,
         })
     }
 
+    /// Records every frame the device looks at and everything it sends.
+    pub fn record_to(&mut self, recorder: SessionRecorder) {
+        self.recorder = Some(recorder);
+    }
+
+    fn send_control(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if let Some(recorder) = &self.recorder {
+            recorder.record_control(bytes);
+        }
+        self.pipeline.control_stream.write_all(bytes)
+    }
+
     fn serialize_taps<I>(screen_width: usize, screen_height: usize, taps: I) -> Vec<u8>
     where
         I: IntoIterator<Item = (u32, u32)>,
@@ -483,3 +1495,514 @@ impl ScrcpyDevice {
         payload
     }
 }
+/// Slower alternative to [`ScrcpyDevice`] that needs nothing but adb. Takes a
+/// screenshot with `adb exec-out screencap` for every frame and taps with
+/// `adb shell input tap`.
+#[derive(Debug)]
+pub struct ScreencapDevice {
+    profile: Profile,
+    layout: Layout,
+    frame: Frame,
+    /// The output of `screencap` for `frame`, which only gets turned into
+    /// `lumas` when it's detected.
+    screenshot: Vec<u8>,
+    lumas: Vec<u8>,
+    /// Whether the board in `lumas` was already detected, in which case the
+    /// next detection takes a new screenshot.
+    detected: bool,
+    recorder: Option<SessionRecorder>,
+}
+
+impl Device for ScreencapDevice {
+    fn detect_screen(&mut self) -> Result<Detection, DetectError> {
+        if self.detected {
+            self.capture()?;
+        }
+        self.detected = true;
+        self.convert_screenshot()?;
+        if let Some(recorder) = &self.recorder {
+            recorder.record_frame(
+                &self.frame,
+                self.layout.screen_width,
+                self.layout.screen_height,
+                1,
+                Some(&self.lumas),
+            );
+        }
+        Ok(Detection {
+            frame: self.frame,
+            screen: self.layout.detect_screen(&self.lumas),
+        })
+    }
+
+    fn wait_for_frame_after(&mut self, instant: Instant) -> Result<Frame, DeviceError> {
+        if self.frame.captured_at <= instant {
+            self.capture()?;
+        }
+        Ok(self.frame)
+    }
+
+    /// Every detection takes a new screenshot already, which is slow enough to
+    /// not need any waiting.
+    fn wait_for_next_frame(
+        &mut self,
+        _frame: &Frame,
+        _timeout: Duration,
+    ) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    fn tap_board(&mut self, taps: Hex<usize>) -> Result<(), DeviceError> {
+        let taps = taps
+            .enumerate()
+            .zip(self.layout.arrow_tap_positions.enumerate())
+            .flat_map(|((&n, _), (&(x, y), _))| repeat_n((x, y), n));
+        self.input_taps(taps)
+    }
+
+    fn tap_claim_button(&mut self) -> Result<(), DeviceError> {
+        self.input_taps(once((
+            self.layout.claim_button_x,
+            self.layout.claim_button_y,
+        )))
+    }
+
+    fn press_back(&mut self) -> Result<(), DeviceError> {
+        if let Some(recorder) = &self.recorder {
+            recorder.record_control(&ScrcpyDevice::PRESS_BACK);
+        }
+        let status = Command::new("adb")
+            .args(["shell", "input keyevent KEYCODE_BACK"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .and_then(|mut c| c.wait())
+            .io_context("run adb shell input keyevent")?;
+        check_status("adb shell input keyevent", status)
+    }
+
+    fn reconnect(&mut self) -> Result<(), DeviceError> {
+        let status = Command::new("adb")
+            .args(["wait-for-device"])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .and_then(|mut c| c.wait())
+            .io_context("wait for android device")?;
+        check_status("adb wait-for-device", status)
+    }
+}
+
+impl ScreencapDevice {
+    pub fn new(profile: Profile) -> Result<ScreencapDevice, LayoutError> {
+        let (width, height) = profile
+            .orientation
+            .unwrap_or(Orientation::Portrait)
+            .rotate_size(profile.screen_width, profile.screen_height);
+        let layout = profile.layout(width, height, 1)?;
+        let lumas = vec![0u8; profile.screen_width * profile.screen_height];
+        Ok(ScreencapDevice {
+            profile,
+            layout,
+            frame: Frame {
+                sequence: 0,
+                captured_at: Instant::now(),
+            },
+            screenshot: vec![],
+            lumas,
+            detected: true,
+            recorder: None,
+        })
+    }
+
+    /// Records every frame the device looks at, and its taps as the control
+    /// messages [`ScrcpyDevice`] would send for them.
+    pub fn record_to(&mut self, recorder: SessionRecorder) {
+        self.recorder = Some(recorder);
+    }
+
+    fn capture(&mut self) -> Result<(), DeviceError> {
+        let captured_at = Instant::now();
+        let output = Command::new("adb")
+            .args(["exec-out", "screencap"])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .io_context("run adb exec-out screencap")?;
+        check_status("adb exec-out screencap", output.status)?;
+        self.screenshot = output.stdout;
+        self.frame = Frame {
+            sequence: self.frame.sequence + 1,
+            captured_at,
+        };
+        self.detected = false;
+        Ok(())
+    }
+
+    /// Turns `screenshot` into `lumas`, and lays the screen out again if the
+    /// device got rotated.
+    fn convert_screenshot(&mut self) -> Result<(), DetectError> {
+        const PIXEL_FORMAT_RGBA_8888: u32 = 1;
+        const PIXEL_FORMAT_RGBX_8888: u32 = 2;
+
+        // The header is the width, height and pixel format, followed by the
+        // color space since Android 9, all as little endian u32
+        let screenshot = &self.screenshot;
+        let pixels_len = 4 * self.profile.screen_width * self.profile.screen_height;
+        let header_len = screenshot
+            .len()
+            .checked_sub(pixels_len)
+            .filter(|len| [12, 16].contains(len))
+            .ok_or_else(|| {
+                DetectError::Frame(format!(
+                    "got a {} byte screenshot for a {}x{} screen",
+                    screenshot.len(),
+                    self.profile.screen_width,
+                    self.profile.screen_height
+                ))
+            })?;
+        let header =
+            |i: usize| u32::from_le_bytes(screenshot[4 * i..4 * i + 4].try_into().unwrap());
+        let (width, height, format) = (header(0) as usize, header(1) as usize, header(2));
+        if (width, height) != (self.layout.screen_width, self.layout.screen_height) {
+            // The device got rotated
+            self.layout = self.profile.layout(width, height, 1)?;
+        }
+        if format != PIXEL_FORMAT_RGBA_8888 && format != PIXEL_FORMAT_RGBX_8888 {
+            return Err(DetectError::Frame(format!(
+                "unsupported pixel format {}",
+                format
+            )));
+        }
+
+        // BT.601 limited range, the same as the video the scrcpy server
+        // encodes, so that the lumas match the ones `ArrowSampler` expects
+        let rgbas = screenshot[header_len..].chunks_exact(4);
+        for (luma, rgba) in self.lumas.iter_mut().zip(rgbas) {
+            let [r, g, b] = [rgba[0], rgba[1], rgba[2]].map(u32::from);
+            *luma = (16 + ((66 * r + 129 * g + 25 * b + 128) >> 8)) as u8;
+        }
+        Ok(())
+    }
+
+    fn input_taps<I>(&self, taps: I) -> Result<(), DeviceError>
+    where
+        I: IntoIterator<Item = (u32, u32)>,
+    {
+        let taps = taps.into_iter().collect_vec();
+        if let Some(recorder) = &self.recorder {
+            recorder.record_control(&ScrcpyDevice::serialize_taps(
+                self.layout.screen_width,
+                self.layout.screen_height,
+                taps.iter().copied(),
+            ));
+        }
+
+        // One shell for all the taps, since starting one takes a while
+        let script = taps
+            .into_iter()
+            .map(|(x, y)| format!("input tap {} {}", x, y))
+            .join("; ");
+        if script.is_empty() {
+            return Ok(());
+        }
+        let status = Command::new("adb")
+            .args(["shell", &script])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .and_then(|mut c| c.wait())
+            .io_context("run adb shell input tap")?;
+        check_status("adb shell input tap", status)
+    }
+}
+
+/// A recorded frame that has its lumas.
+#[derive(Debug, Clone)]
+struct ReplayFrame {
+    t: f64,
+    width: usize,
+    height: usize,
+    size_divider: usize,
+    path: PathBuf,
+}
+
+/// Where a replay sent something other than what the recorded session sent.
+#[derive(Debug, Clone, PartialEq, Error)]
+#[error(
+    "control message {index} diverged at {t:.3}s: want {}, but got {}",
+    expected.as_deref().unwrap_or("nothing"),
+    actual.as_deref().unwrap_or("nothing")
+)]
+pub struct Divergence {
+    /// Of the control message among the ones sent.
+    pub index: usize,
+    /// Seconds into the replay.
+    pub t: f64,
+    /// The recorded control bytes, in hex, if the recording sent any more.
+    pub expected: Option<String>,
+    /// The control bytes sent in the replay, in hex, if it sent any more.
+    pub actual: Option<String>,
+}
+
+/// Plays back a session recorded with [`SessionRecorder`], to test changes to
+/// the solver, the detection or the player offline. Shows the recorded frames
+/// at the times they were recorded, on a clock of its own that only moves on
+/// while `play` waits for frames, so that the player's timeouts work out the
+/// same every time without having to actually wait. Checks every tap against
+/// the control bytes that were recorded. Lays the screen out with `profile`
+/// again rather than replaying the recorded detections.
+#[derive(Debug)]
+pub struct ReplayDevice {
+    profile: Profile,
+    frames: Vec<ReplayFrame>,
+    /// When the first frame was recorded.
+    t0: f64,
+    /// When the last event was recorded.
+    t_end: f64,
+    started_at: Instant,
+    /// How far into the recording the replay is, which is where its clock
+    /// stands.
+    elapsed: Duration,
+    /// The index of the frame on screen, and its layout.
+    current: Option<(usize, Layout)>,
+    lumas: Vec<u8>,
+    controls: Vec<String>,
+    controls_sent: usize,
+    divergence: Option<Divergence>,
+    done: Arc<AtomicBool>,
+}
+
+impl Device for ReplayDevice {
+    fn now(&self) -> Instant {
+        self.started_at + self.elapsed
+    }
+
+    fn detect_screen(&mut self) -> Result<Detection, DetectError> {
+        self.show_latest_frame()?;
+        let (i, layout) = self.current.as_ref().expect("a frame is on screen");
+        Ok(Detection {
+            frame: self.frame(*i),
+            screen: layout.detect_screen(&self.lumas),
+        })
+    }
+
+    fn wait_for_frame_after(&mut self, instant: Instant) -> Result<Frame, DeviceError> {
+        const TIMEOUT: Duration = Duration::from_millis(500);
+
+        let next = (0..self.frames.len())
+            .map(|i| self.frame(i))
+            .find(|frame| frame.captured_at > instant);
+        let until = match next {
+            Some(frame) => frame.captured_at.min(instant + TIMEOUT),
+            None => instant + TIMEOUT,
+        };
+        self.advance_to(until);
+        Ok(self.frame(self.latest_frame()))
+    }
+
+    fn wait_for_next_frame(&mut self, frame: &Frame, timeout: Duration) -> Result<(), DeviceError> {
+        let deadline = self.now() + timeout;
+        let until = match self.frames.get(frame.sequence as usize) {
+            Some(_) => self
+                .frame(frame.sequence as usize)
+                .captured_at
+                .min(deadline),
+            None => deadline,
+        };
+        self.advance_to(until);
+        Ok(())
+    }
+
+    fn tap_board(&mut self, taps: Hex<usize>) -> Result<(), DeviceError> {
+        let layout = self.layout()?;
+        let taps = taps
+            .enumerate()
+            .zip(layout.arrow_tap_positions.enumerate())
+            .flat_map(|((&n, _), (&(x, y), _))| repeat_n((x, y), n));
+        let taps = ScrcpyDevice::serialize_taps(layout.screen_width, layout.screen_height, taps);
+        self.check_control(&taps);
+        Ok(())
+    }
+
+    fn tap_claim_button(&mut self) -> Result<(), DeviceError> {
+        let layout = self.layout()?;
+        let taps = ScrcpyDevice::serialize_taps(
+            layout.screen_width,
+            layout.screen_height,
+            once((layout.claim_button_x, layout.claim_button_y)),
+        );
+        self.check_control(&taps);
+        Ok(())
+    }
+
+    fn press_back(&mut self) -> Result<(), DeviceError> {
+        self.check_control(&ScrcpyDevice::PRESS_BACK);
+        Ok(())
+    }
+
+    /// The recording goes on regardless.
+    fn reconnect(&mut self) -> Result<(), DeviceError> {
+        Ok(())
+    }
+}
+
+impl ReplayDevice {
+    /// Frames are only read once they come up, so a missing or broken one
+    /// fails the detection that needs it.
+    pub fn new(profile: Profile, dir: &Path) -> Result<ReplayDevice, SessionError> {
+        let (_, events) = read_session(dir)?;
+        let frames = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Frame {
+                    t,
+                    width,
+                    height,
+                    size_divider,
+                    lumas: Some(path),
+                    ..
+                } => Some(ReplayFrame {
+                    t: *t,
+                    width: *width,
+                    height: *height,
+                    size_divider: *size_divider,
+                    path: dir.join(path),
+                }),
+                _ => None,
+            })
+            .collect_vec();
+        let controls = events
+            .iter()
+            .filter_map(|event| match event {
+                Event::Control { bytes, .. } => Some(bytes.clone()),
+                _ => None,
+            })
+            .collect_vec();
+        let t0 = frames.first().ok_or(SessionError::NoFrames)?.t;
+        let t_end = events.iter().map(Event::t).fold(t0, f64::max);
+
+        Ok(ReplayDevice {
+            profile,
+            frames,
+            t0,
+            t_end,
+            started_at: Instant::now(),
+            elapsed: Duration::ZERO,
+            current: None,
+            lumas: vec![],
+            controls,
+            controls_sent: 0,
+            divergence: None,
+            done: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Gets set once the recording is over or the replay diverged, so that
+    /// `play` can stop.
+    pub fn done(&self) -> Arc<AtomicBool> {
+        self.done.clone()
+    }
+
+    /// The first divergence from the recording, including control messages
+    /// that the recording sent but the replay never got to.
+    pub fn finish(&self) -> Result<(), Divergence> {
+        if let Some(divergence) = &self.divergence {
+            return Err(divergence.clone());
+        }
+        match self.controls.get(self.controls_sent) {
+            Some(expected) => Err(Divergence {
+                index: self.controls_sent,
+                t: self.elapsed.as_secs_f64(),
+                expected: Some(expected.clone()),
+                actual: None,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    fn frame(&self, i: usize) -> Frame {
+        Frame {
+            sequence: i as u64 + 1,
+            captured_at: self.started_at + Duration::from_secs_f64(self.frames[i].t - self.t0),
+        }
+    }
+
+    /// Moves the clock on to `instant`, unless it's already past it.
+    fn advance_to(&mut self, instant: Instant) {
+        self.elapsed = self
+            .elapsed
+            .max(instant.saturating_duration_since(self.started_at));
+    }
+
+    /// The index of the last frame recorded by now. Sets `done` once the
+    /// recording is over.
+    fn latest_frame(&self) -> usize {
+        let elapsed = self.elapsed.as_secs_f64();
+        if elapsed > self.t_end - self.t0 {
+            self.done.store(true, Ordering::Relaxed);
+        }
+        self.frames
+            .iter()
+            .rposition(|frame| frame.t - self.t0 <= elapsed)
+            .unwrap_or(0)
+    }
+
+    /// The layout of the frame on screen, which taps are relative to.
+    fn layout(&self) -> Result<&Layout, DeviceError> {
+        match &self.current {
+            Some((_, layout)) => Ok(layout),
+            None => Err(DeviceError::Unexpected(
+                "tapped before any frame was on screen".into(),
+            )),
+        }
+    }
+
+    /// Puts the last frame recorded by now on screen.
+    fn show_latest_frame(&mut self) -> Result<(), DetectError> {
+        let latest = self.latest_frame();
+        if matches!(self.current, Some((i, _)) if i == latest) {
+            return Ok(());
+        }
+
+        let frame = &self.frames[latest];
+        let lumas = read(&frame.path).io_context(format!("read {}", frame.path.display()))?;
+        if lumas.len() != frame.width * frame.height {
+            return Err(DetectError::Frame(format!(
+                "got {} lumas in {} for a {}x{} frame",
+                lumas.len(),
+                frame.path.display(),
+                frame.width,
+                frame.height
+            )));
+        }
+        let layout = self
+            .profile
+            .layout(frame.width, frame.height, frame.size_divider)?;
+        self.lumas = lumas;
+        self.current = Some((latest, layout));
+        Ok(())
+    }
+
+    fn check_control(&mut self, bytes: &[u8]) {
+        let index = self.controls_sent;
+        self.controls_sent += 1;
+        if self.divergence.is_some() {
+            return;
+        }
+        let actual = bytes_to_hex(bytes);
+        let expected = self.controls.get(index);
+        if expected != Some(&actual) {
+            self.divergence = Some(Divergence {
+                index,
+                t: self.elapsed.as_secs_f64(),
+                expected: expected.cloned(),
+                actual: Some(actual),
+            });
+            self.done.store(true, Ordering::Relaxed);
+        }
+    }
+}
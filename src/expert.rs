@@ -1,4 +1,4 @@
-use std::fmt::Display;
+use core::{array, fmt::Display, str::FromStr};
 
 use thiserror::Error;
 
@@ -10,6 +10,16 @@ pub enum ArrowFromU8Error {
     OutOfRange(u8),
 }
 
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum BoardFromStrError {
+    #[error("want digits, but got {0:?}")]
+    NotADigit(char),
+    #[error(transparent)]
+    Arrow(#[from] ArrowFromU8Error),
+    #[error("want 37 arrows, but got {0}")]
+    Length(usize),
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Arrow(pub u8);
 
@@ -26,7 +36,7 @@ impl TryFrom<u8> for Arrow {
 }
 
 impl Display for Arrow {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "{}", self.0)
     }
 }
@@ -34,22 +44,17 @@ impl Display for Arrow {
 impl Arrow {
     const UP: Arrow = Arrow(0);
 
-    fn rotate(&mut self) {
-        self.0 = (self.0 + 1) % 6;
-    }
-
-    fn distance_to(self, other: Arrow) -> usize {
-        let a: isize = self.0.into();
-        let b: isize = other.0.into();
-        (b - a).rem_euclid(6).try_into().unwrap()
+    const fn distance_to(self, other: Arrow) -> usize {
+        (other.0 as isize - self.0 as isize).rem_euclid(6) as usize
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Board(Hex<Arrow>);
 
+#[cfg(feature = "alloc")]
 impl Display for Board {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         let s = self.0.visualize(|a| {
             match a {
                 Arrow(0) => "0 ",
@@ -66,159 +71,1003 @@ impl Display for Board {
     }
 }
 
+/// Parses 37 digits in the order of [`Hex::POSITIONS`]. Ignores whitespace,
+/// so that rows can be spaced apart.
+impl FromStr for Board {
+    type Err = BoardFromStrError;
+
+    fn from_str(s: &str) -> Result<Board, BoardFromStrError> {
+        let mut arrows = [Arrow::UP; 37];
+        let mut len = 0;
+        for c in s.chars().filter(|c| !c.is_whitespace()) {
+            let digit = c.to_digit(10).ok_or(BoardFromStrError::NotADigit(c))?;
+            if let Some(a) = arrows.get_mut(len) {
+                *a = Arrow::try_from(digit as u8)?;
+            }
+            len += 1;
+        }
+        if len != arrows.len() {
+            return Err(BoardFromStrError::Length(len));
+        }
+        let mut arrows = arrows.into_iter();
+        Ok(Board(Hex::from_fn(|_, _| arrows.next().unwrap())))
+    }
+}
+
 impl Board {
     pub fn new(arrows: Hex<Arrow>) -> Board {
         Board(arrows)
     }
 
+    pub fn arrows(&self) -> &Hex<Arrow> {
+        &self.0
+    }
+
     pub fn is_solved(&self) -> bool {
         self.0.enumerate().all(|(&a, _)| a == Arrow::UP)
     }
 
-    fn poke(&mut self, p: Position) {
-        const DS: [(i64, i64); 7] = [(-1, -1), (0, -1), (-1, 0), (0, 0), (1, 0), (0, 1), (1, 1)];
+    /// Whether every arrow points the same way as in `target`.
+    pub fn is_solved_to(&self, target: &Board) -> bool {
+        self == target
+    }
+
+    /// See [`PackedBoard::can_reach`].
+    pub fn can_reach(&self, target: &Board) -> bool {
+        PackedBoard::from(self).can_reach(target.into())
+    }
+
+    /// See [`PackedBoard::is_solvable`].
+    pub fn is_solvable(&self) -> bool {
+        PackedBoard::from(self).is_solvable()
+    }
+
+    /// See [`PackedBoard::solutions`].
+    pub fn solutions(&self) -> impl ExactSizeIterator<Item = Hex<usize>> {
+        PackedBoard::from(self).solutions().map(Hex::from)
+    }
+
+    /// See [`PackedBoard::poke_all`].
+    pub fn poke_all(&mut self, pokes: &Hex<usize>) {
+        let mut packed = PackedBoard::from(&*self);
+        packed.poke_all(pokes.into());
+        *self = packed.into();
+    }
 
+    /// Assuming `planned` pokes were sent for this board but only some of them
+    /// registered, returns the ones that were dropped on the way to
+    /// `observed`. `None` if dropping pokes can't explain `observed`.
+    pub fn dropped_pokes(&self, planned: &Hex<usize>, observed: &Board) -> Option<Hex<usize>> {
+        let planned = PackedPlan::from(planned);
+        let mut expected = PackedBoard::from(self);
+        expected.poke_all(planned);
+
+        // The dropped pokes take `observed` to where the plan would have left
+        // the board. Many plans do, so keep the fewest pokes that fit in the
+        // plan.
+        PackedBoard::from(observed)
+            .relative_to(expected)
+            .solutions()
+            .filter(|dropped| (0..POKE_MASKS.len()).all(|c| dropped.count(c) <= planned.count(c)))
+            .min_by_key(|dropped| dropped.total())
+            .map(Hex::from)
+    }
+
+    /// Solves the board in all 12 orientations and keeps the fewest pokes.
+    /// See [`PackedBoard::solve`].
+    pub fn solve(self) -> Hex<usize> {
+        PackedBoard::from(&self).solve().into()
+    }
+
+    /// See [`PackedBoard::solve_linear`].
+    pub fn solve_linear(self) -> Hex<usize> {
+        PackedBoard::from(&self).solve_linear().into()
+    }
+
+    /// See [`PackedBoard::solve_steps`].
+    pub fn solve_steps(&self) -> SolveSteps {
+        PackedBoard::from(self).solve_steps()
+    }
+
+    /// See [`PackedBoard::solve_to`].
+    pub fn solve_to(self, target: &Board) -> Hex<usize> {
+        PackedBoard::from(&self).solve_to(target.into()).into()
+    }
+}
+
+/// Bands of cells that the solver aligns one after another, each by poking
+/// the next. [`PackedBoard::solve_steps`] labels every poke with the group of
+/// the poked cell, or the fixup.
+///
+/// | Groups
+/// |          G0
+/// |       G0    G0
+/// |    G0    G1    G0
+/// | G0    G1    G1    G0
+/// |    G1    G2    G1
+/// | G1    G2    G2    G1
+/// |    G2    G3    G2
+/// | G2    G3    G3    G2
+/// |    G3    G4    G3
+/// | G3    G4    G4    G3
+/// |    G4    G5    G4
+/// |       G5    G5
+/// |          G6
+///
+/// A cell's group is the smaller of its coordinates, in whichever orientation
+/// the board gets solved in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Group {
+    G0,
+    G1,
+    G2,
+    G3,
+    G4,
+    G5,
+    G6,
+    Fixup,
+}
+
+impl Display for Group {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Group::Fixup => write!(f, "fixup"),
+            &group => write!(f, "G{}", group as u8),
+        }
+    }
+}
+
+impl Group {
+    fn of(p: Position) -> Group {
+        const GROUPS: [Group; 7] = [
+            Group::G0,
+            Group::G1,
+            Group::G2,
+            Group::G3,
+            Group::G4,
+            Group::G5,
+            Group::G6,
+        ];
         let (x, y) = p.as_xy();
-        let x: i64 = x.try_into().unwrap();
-        let y: i64 = y.try_into().unwrap();
-        let xys = DS.into_iter().flat_map(|(dx, dy)| {
-            let x: usize = (x + dx).try_into().ok()?;
-            let y: usize = (y + dy).try_into().ok()?;
-            Some((x, y))
-        });
-        for (x, y) in xys {
-            if let Some(a) = self.0.at_mut(x, y) {
-                a.rotate();
-            }
-        }
-    }
-
-    fn solve_this_orientation(mut self) -> Hex<usize> {
-        fn partially_solve(b: &mut Board, poke_counts: &mut Hex<usize>) {
-            use crate::hex::positions::*;
-
-            /// Groups
-            /// |          G0
-            /// |       G0    G0
-            /// |    G0    G1    G0
-            /// | G0    G1    G1    G0
-            /// |    G1    G2    G1
-            /// | G1    G2    G2    G1
-            /// |    G2    G3    G2
-            /// | G2    G3    G3    G2
-            /// |    G3    G4    G3
-            /// | G3    G4    G4    G3
-            /// |    G4    G5    G4
-            /// |       G5    G5
-            /// |          G6
-            const PARTIAL_SOLVE_MOVES: [(Position, Position); 30] = [
-                // Align group 0 by poking group 1
-                (A0, B1),
-                (A1, B2),
-                (A2, B3),
-                (A3, B4),
-                (B0, C1),
-                (C0, D1),
-                (D0, E1),
-                // Align group 1 by poking group 2
-                (B1, C2),
-                (B2, C3),
-                (B3, C4),
-                (B4, C5),
-                (C1, D2),
-                (D1, E2),
-                (E1, F2),
-                // Align group 2 by poking group 3
-                (C2, D3),
-                (C3, D4),
-                (C4, D5),
-                (C5, D6),
-                (D2, E3),
-                (E2, F3),
-                (F2, G3),
-                // Align group 3 by poking group 4
-                (D3, E4),
-                (D4, E5),
-                (D5, E6),
-                (E3, F4),
-                (F3, G4),
-                // Align group 4 by poking group 5
-                (E4, F5),
-                (E5, F6),
-                (F4, G5),
-                // Align group 5 by poking group 6
-                (F5, G6),
-            ];
-
-            for (solvee, poke) in PARTIAL_SOLVE_MOVES {
-                let solvee = b.0[solvee];
-                let poke_count = solvee.distance_to(Arrow::UP);
-                poke_counts[poke] += poke_count;
-                for _ in 0..poke_count {
-                    b.poke(poke);
+        GROUPS[x.min(y)]
+    }
+}
+
+/// Aligns the cells of every [`Group`] by poking the next one. Each move
+/// pokes the second cell until the first one points up.
+const PARTIAL_SOLVE_MOVES: [(Position, Position); 30] = {
+    use crate::hex::positions::*;
+    [
+        // Align group 0 by poking group 1
+        (A0, B1),
+        (A1, B2),
+        (A2, B3),
+        (A3, B4),
+        (B0, C1),
+        (C0, D1),
+        (D0, E1),
+        // Align group 1 by poking group 2
+        (B1, C2),
+        (B2, C3),
+        (B3, C4),
+        (B4, C5),
+        (C1, D2),
+        (D1, E2),
+        (E1, F2),
+        // Align group 2 by poking group 3
+        (C2, D3),
+        (C3, D4),
+        (C4, D5),
+        (C5, D6),
+        (D2, E3),
+        (E2, F3),
+        (F2, G3),
+        // Align group 3 by poking group 4
+        (D3, E4),
+        (D4, E5),
+        (D5, E6),
+        (E3, F4),
+        (F3, G4),
+        // Align group 4 by poking group 5
+        (E4, F5),
+        (E5, F6),
+        (F4, G5),
+        // Align group 5 by poking group 6
+        (F5, G6),
+    ]
+};
+
+/// After partially solving, only these cells can be left unaligned. The fixup
+/// reads them...
+const FIXUP_SOLVEES: [Position; 3] = {
+    use crate::hex::positions::*;
+    [D6, E6, F6]
+};
+
+/// ...and pokes these, after which partially solving again solves the board.
+const FIXUP_POKES: [Position; 4] = {
+    use crate::hex::positions::*;
+    [A0, A1, A2, A3]
+};
+
+/// The lowest of the 3 bits of every cell in a [`PackedBoard`].
+const LOW_BITS: u128 = {
+    let mut bits = 0;
+    let mut i = 0;
+    while i < 37 {
+        bits |= 1 << (3 * i);
+        i += 1;
+    }
+    bits
+};
+
+/// For every cell, the lowest bits of the cell and its neighbors, which is
+/// what poking it turns.
+const POKE_MASKS: [u128; 37] = {
+    const DS: [(isize, isize); 7] = [(-1, -1), (0, -1), (-1, 0), (0, 0), (1, 0), (0, 1), (1, 1)];
+
+    let positions = Hex::<Arrow>::POSITIONS;
+    let mut masks = [0; 37];
+    let mut i = 0;
+    while i < 37 {
+        let (x, y) = positions[i].as_xy();
+        let mut j = 0;
+        while j < 37 {
+            let (nx, ny) = positions[j].as_xy();
+            let (dx, dy) = (nx as isize - x as isize, ny as isize - y as isize);
+            let mut d = 0;
+            while d < DS.len() {
+                if DS[d].0 == dx && DS[d].1 == dy {
+                    masks[i] |= 1 << (3 * j);
                 }
+                d += 1;
             }
+            j += 1;
         }
+        i += 1;
+    }
+    masks
+};
 
-        fn fixup(b: &mut Board, poke_counts: &mut Hex<usize>) {
-            use crate::hex::positions::*;
+/// Adds `n` modulo 6 to every 3 bit number of `bits` that `mask` has the
+/// lowest bit of.
+const fn add_mod_6(mut bits: u128, mask: u128, n: usize) -> u128 {
+    // Adding at most 2 to a number within [0, 6) keeps it below 8, so nothing
+    // carries into the next number
+    let mut twos = n % 6 / 2;
+    while twos > 0 {
+        bits = wrap_6(bits + (mask << 1));
+        twos -= 1;
+    }
+    if n % 2 == 1 {
+        bits = wrap_6(bits + mask);
+    }
+    bits
+}
 
-            let d6 = b.0[D6];
-            let e6 = b.0[E6];
-            let f6 = b.0[F6];
+/// Takes 6 off every 3 bit number of `bits` that's 6 or 7.
+const fn wrap_6(bits: u128) -> u128 {
+    let wrapped = (bits >> 1) & (bits >> 2) & LOW_BITS;
+    bits - (wrapped << 1) - (wrapped << 2)
+}
 
-            let a_poke_count = Arrow::UP.distance_to(e6) + d6.distance_to(Arrow::UP);
-            let b_d_poke_count = e6.distance_to(Arrow::UP);
-            let c_poke_count = if (d6.0 + f6.0) % 2 == 0 { 0 } else { 3 };
+/// The moves that solve the board in one of its 12 orientations, as cells of
+/// the board in its original orientation. Rotating and flipping keeps
+/// neighbors neighbors, so these solve the original board as is.
+#[derive(Debug, Clone, Copy)]
+struct Moves {
+    partially_solve: [(usize, usize); 30],
+    fixup_solvees: [usize; 3],
+    fixup_pokes: [usize; 4],
+}
 
-            let fixup_pokes = [
-                (A0, a_poke_count),
-                (A1, b_d_poke_count),
-                (A2, c_poke_count),
-                (A3, b_d_poke_count),
-            ];
-            for (poke, poke_count) in fixup_pokes {
-                poke_counts[poke] += poke_count;
-                for _ in 0..poke_count {
-                    b.poke(poke);
+/// In the order that [`PackedBoard::solve`] tries them, which breaks ties.
+const ORIENTATIONS: [Moves; 12] = {
+    const fn cell(orientation: &[usize; 37], p: Position) -> usize {
+        orientation[Hex::<Arrow>::cell(p)]
+    }
+
+    let mut orientations = [Moves {
+        partially_solve: [(0, 0); 30],
+        fixup_solvees: [0; 3],
+        fixup_pokes: [0; 4],
+    }; 12];
+    let mut o = 0;
+    while o < 12 {
+        let (flipped, rotation) = (o / 6, o % 6);
+        // Cell `i` of the oriented board is cell `orientation[i]` of the
+        // original
+        let mut orientation = [0; 37];
+        let mut i = 0;
+        while i < 37 {
+            let mut cell = i;
+            let mut r = 0;
+            while r < rotation {
+                cell = Hex::<Arrow>::CELLS_ROTATED_60[cell];
+                r += 1;
+            }
+            if flipped == 1 {
+                cell = Hex::<Arrow>::CELLS_FLIPPED[cell];
+            }
+            orientation[i] = cell;
+            i += 1;
+        }
+
+        let moves = &mut orientations[o];
+        let mut m = 0;
+        while m < 30 {
+            let (solvee, poke) = PARTIAL_SOLVE_MOVES[m];
+            moves.partially_solve[m] = (cell(&orientation, solvee), cell(&orientation, poke));
+            m += 1;
+        }
+        let mut m = 0;
+        while m < 3 {
+            moves.fixup_solvees[m] = cell(&orientation, FIXUP_SOLVEES[m]);
+            m += 1;
+        }
+        let mut m = 0;
+        while m < 4 {
+            moves.fixup_pokes[m] = cell(&orientation, FIXUP_POKES[m]);
+            m += 1;
+        }
+        o += 1;
+    }
+    orientations
+};
+
+/// A [`Board`] with 3 bits per arrow, for solving lots of boards quickly.
+/// Cells are indices into [`Hex::POSITIONS`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PackedBoard(u128);
+
+/// How many times to poke every cell, modulo 6, packed like [`PackedBoard`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct PackedPlan(u128);
+
+impl From<&Board> for PackedBoard {
+    fn from(board: &Board) -> PackedBoard {
+        let mut bits = 0;
+        for (i, (a, _)) in board.0.enumerate().enumerate() {
+            bits |= u128::from(a.0 % 6) << (3 * i);
+        }
+        PackedBoard(bits)
+    }
+}
+
+impl From<PackedBoard> for Board {
+    fn from(packed: PackedBoard) -> Board {
+        let mut cells = 0..;
+        Board(Hex::from_fn(|_, _| packed.arrow(cells.next().unwrap())))
+    }
+}
+
+impl From<&Hex<usize>> for PackedPlan {
+    fn from(poke_counts: &Hex<usize>) -> PackedPlan {
+        let mut plan = PackedPlan::default();
+        for (i, (&n, _)) in poke_counts.enumerate().enumerate() {
+            plan.add(i, n);
+        }
+        plan
+    }
+}
+
+impl From<PackedPlan> for Hex<usize> {
+    fn from(plan: PackedPlan) -> Hex<usize> {
+        let mut cells = 0..;
+        Hex::from_fn(|_, _| plan.count(cells.next().unwrap()))
+    }
+}
+
+impl PackedPlan {
+    pub const fn count(self, cell: usize) -> usize {
+        ((self.0 >> (3 * cell)) & 0b111) as usize
+    }
+
+    /// How many pokes the plan takes.
+    pub fn total(self) -> usize {
+        let ones = |shift: u32| (self.0 >> shift & LOW_BITS).count_ones() as usize;
+        ones(0) + 2 * ones(1) + 4 * ones(2)
+    }
+
+    const fn add(&mut self, cell: usize, n: usize) {
+        self.0 = add_mod_6(self.0, 1 << (3 * cell), n);
+    }
+}
+
+impl PackedBoard {
+    pub const fn arrow(self, cell: usize) -> Arrow {
+        Arrow(((self.0 >> (3 * cell)) & 0b111) as u8)
+    }
+
+    pub fn is_solved(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether any plan solves the board. Boards straight from the game
+    /// always are, so one that isn't was misdetected.
+    pub fn is_solvable(self) -> bool {
+        KERNELS.iter().all(|kernel| kernel.reaches(self))
+    }
+
+    /// Every plan that solves the board, modulo 6, starting with
+    /// [`PackedBoard::solve`]'s. None if the board isn't solvable.
+    pub fn solutions(self) -> Solutions {
+        let len = if self.is_solvable() {
+            KERNELS.iter().map(Kernel::size).product()
+        } else {
+            0
+        };
+        Solutions {
+            solution: self.solve(),
+            next: 0,
+            len,
+        }
+    }
+
+    /// Pokes `cell` `n` times.
+    pub const fn poke(&mut self, cell: usize, n: usize) {
+        self.0 = add_mod_6(self.0, POKE_MASKS[cell], n);
+    }
+
+    pub fn poke_all(&mut self, plan: PackedPlan) {
+        for cell in 0..POKE_MASKS.len() {
+            self.poke(cell, plan.count(cell));
+        }
+    }
+
+    /// Pokes turn every arrow the same amount no matter where it points, so
+    /// solving toward `target` is solving how far every arrow is from it.
+    /// Like [`PackedBoard::solve`], this plans pokes even if `target` can't be
+    /// reached, so check with [`PackedBoard::can_reach`] first.
+    pub fn solve_to(self, target: PackedBoard) -> PackedPlan {
+        self.relative_to(target).solve()
+    }
+
+    /// Whether any plan turns the board into `target`.
+    pub fn can_reach(self, target: PackedBoard) -> bool {
+        self.relative_to(target).is_solvable()
+    }
+
+    /// How far every arrow is turned from the same one in `target`. Solved if
+    /// the boards are the same.
+    pub fn relative_to(self, target: PackedBoard) -> PackedBoard {
+        let mut bits = 0;
+        for cell in 0..POKE_MASKS.len() {
+            let distance = target.arrow(cell).distance_to(self.arrow(cell));
+            bits |= (distance as u128) << (3 * cell);
+        }
+        PackedBoard(bits)
+    }
+
+    /// Solves the board in all 12 orientations and keeps the fewest pokes, the
+    /// first orientation winning ties.
+    pub fn solve(self) -> PackedPlan {
+        self.best_orientation().1
+    }
+
+    /// Which of [`ORIENTATIONS`] [`PackedBoard::solve`] picks, and its plan.
+    fn best_orientation(self) -> (usize, PackedPlan) {
+        let mut best = (0, self.solve_with(&ORIENTATIONS[0]));
+        for (o, moves) in ORIENTATIONS.iter().enumerate().skip(1) {
+            let plan = self.solve_with(moves);
+            if plan.total() < best.1.total() {
+                best = (o, plan);
+            }
+        }
+        best
+    }
+
+    /// Every single poke that [`PackedBoard::solve`] plans, in the order it
+    /// makes them, with the board after each. Unlike the plan, which is
+    /// modulo 6, a cell can get poked more than 5 times in all.
+    pub fn solve_steps(self) -> SolveSteps {
+        SolveSteps {
+            board: self,
+            moves: ORIENTATIONS[self.best_orientation().0],
+            next_move: 0,
+            fixup_poke_counts: [0; 4],
+            current: (Group::G0, 0, 0),
+        }
+    }
+
+    /// Like [`PackedBoard::solve`], but multiplies the arrows by one
+    /// precomputed matrix per orientation instead of poking. Gives the same
+    /// plans.
+    pub fn solve_linear(self) -> PackedPlan {
+        let arrows: [usize; 37] = array::from_fn(|cell| self.arrow(cell).0.into());
+        let mut best: Option<PackedPlan> = None;
+        for matrix in &SOLVE_MATRICES {
+            let mut plan = PackedPlan::default();
+            for (cell, row) in matrix.iter().enumerate() {
+                let n: usize = row
+                    .iter()
+                    .zip(arrows)
+                    .map(|(&m, a)| usize::from(m) * a)
+                    .sum();
+                plan.0 |= ((n % 6) as u128) << (3 * cell);
+            }
+            if best.is_none_or(|best| plan.total() < best.total()) {
+                best = Some(plan);
+            }
+        }
+        best.unwrap()
+    }
+
+    /// How many times the fixup pokes each of `moves.fixup_pokes`.
+    const fn fixup_poke_counts(self, moves: &Moves) -> [usize; 4] {
+        let [d6, e6, f6] = moves.fixup_solvees;
+        let (d6, e6, f6) = (self.arrow(d6), self.arrow(e6), self.arrow(f6));
+
+        let a_poke_count = Arrow::UP.distance_to(e6) + d6.distance_to(Arrow::UP);
+        let b_d_poke_count = e6.distance_to(Arrow::UP);
+        let c_poke_count = if (d6.0 + f6.0).is_multiple_of(2) {
+            0
+        } else {
+            3
+        };
+        [a_poke_count, b_d_poke_count, c_poke_count, b_d_poke_count]
+    }
+
+    const fn solve_with(mut self, moves: &Moves) -> PackedPlan {
+        const fn partially_solve(b: &mut PackedBoard, plan: &mut PackedPlan, moves: &Moves) {
+            let mut m = 0;
+            while m < moves.partially_solve.len() {
+                let (solvee, poke) = moves.partially_solve[m];
+                let poke_count = b.arrow(solvee).distance_to(Arrow::UP);
+                plan.add(poke, poke_count);
+                b.poke(poke, poke_count);
+                m += 1;
+            }
+        }
+
+        const fn fixup(b: &mut PackedBoard, plan: &mut PackedPlan, moves: &Moves) {
+            let poke_counts = b.fixup_poke_counts(moves);
+            let mut m = 0;
+            while m < moves.fixup_pokes.len() {
+                plan.add(moves.fixup_pokes[m], poke_counts[m]);
+                b.poke(moves.fixup_pokes[m], poke_counts[m]);
+                m += 1;
+            }
+        }
+
+        let mut plan = PackedPlan(0);
+        partially_solve(&mut self, &mut plan, moves);
+        fixup(&mut self, &mut plan, moves);
+        partially_solve(&mut self, &mut plan, moves);
+        plan
+    }
+}
+
+/// A single poke of [`PackedBoard::solve_steps`], and the board after it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub group: Group,
+    pub poke: Position,
+    pub board: Board,
+}
+
+/// See [`PackedBoard::solve_steps`].
+#[derive(Debug, Clone)]
+pub struct SolveSteps {
+    board: PackedBoard,
+    moves: Moves,
+    /// Counts through partially solving, the fixup and partially solving
+    /// again.
+    next_move: usize,
+    fixup_poke_counts: [usize; 4],
+    /// The group and cell being poked, and how many pokes it has left.
+    current: (Group, usize, usize),
+}
+
+impl Iterator for SolveSteps {
+    type Item = Step;
+
+    fn next(&mut self) -> Option<Step> {
+        let partially_solve = |b: PackedBoard, moves: &Moves, m: usize| {
+            let (solvee, poke) = moves.partially_solve[m];
+            let poke_count = b.arrow(solvee).distance_to(Arrow::UP);
+            (Group::of(PARTIAL_SOLVE_MOVES[m].1), poke, poke_count)
+        };
+
+        while self.current.2 == 0 {
+            self.current = match self.next_move {
+                m @ 0..30 => partially_solve(self.board, &self.moves, m),
+                m @ 30..34 => {
+                    // Like in `solve_with`, the fixup decides all of its
+                    // pokes before making any
+                    if m == 30 {
+                        self.fixup_poke_counts = self.board.fixup_poke_counts(&self.moves);
+                    }
+                    let m = m - 30;
+                    let poke_count = self.fixup_poke_counts[m] % 6;
+                    (Group::Fixup, self.moves.fixup_pokes[m], poke_count)
                 }
+                m @ 34..64 => partially_solve(self.board, &self.moves, m - 34),
+                _ => return None,
+            };
+            self.next_move += 1;
+        }
+
+        let (group, cell, poke_count) = &mut self.current;
+        *poke_count -= 1;
+        self.board.poke(*cell, 1);
+        Some(Step {
+            group: *group,
+            poke: Hex::<Arrow>::POSITIONS[*cell],
+            board: self.board.into(),
+        })
+    }
+}
+
+/// [`PackedBoard::solve_with`] for every orientation as a matrix, so that
+/// `plan[i] = sum(matrix[i][j] * arrows[j]) % 6`.
+///
+/// Every step of the solver is linear modulo 6: partially solving pokes
+/// `-arrow` times, and the fixup pokes `e6 - d6`, `-e6` and
+/// `3 * (d6 + f6)` times, since 3 times a number modulo 6 only depends on
+/// whether it's odd. So column `j` is the plan for a board with a single
+/// arrow turned once, at cell `j`.
+static SOLVE_MATRICES: [[[u8; 37]; 37]; 12] = {
+    let mut matrices = [[[0; 37]; 37]; 12];
+    let mut o = 0;
+    while o < 12 {
+        let mut j = 0;
+        while j < 37 {
+            let plan = PackedBoard(1 << (3 * j)).solve_with(&ORIENTATIONS[o]);
+            let mut i = 0;
+            while i < 37 {
+                matrices[o][i][j] = plan.count(i) as u8;
+                i += 1;
             }
+            j += 1;
         }
+        o += 1;
+    }
+    matrices
+};
 
-        let mut poke_counts = Hex::from_fn(|_, _| 0);
-        partially_solve(&mut self, &mut poke_counts);
-        fixup(&mut self, &mut poke_counts);
-        partially_solve(&mut self, &mut poke_counts);
-        for (n, _) in poke_counts.enumerate_mut() {
-            *n %= 6;
+/// The plans that leave every board as it is, modulo a prime factor of 6.
+/// Adding one of them to a plan gives another plan with the same result.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Kernel {
+    pub modulus: u8,
+    /// Tap counts modulo `modulus`, in the order of [`Hex::POSITIONS`].
+    pub basis: &'static [[u8; 37]],
+}
+
+impl Kernel {
+    pub const fn dimension(&self) -> usize {
+        self.basis.len()
+    }
+
+    /// How many distinct plans the kernel has.
+    pub const fn size(&self) -> usize {
+        (self.modulus as usize).pow(self.basis.len() as u32)
+    }
+
+    /// Whether pokes can reach `arrows` modulo `modulus`. Poking is
+    /// symmetric, since neighbors poke each other, so it reaches exactly the
+    /// boards that are orthogonal to its kernel.
+    fn reaches(&self, arrows: PackedBoard) -> bool {
+        self.basis.iter().all(|v| {
+            let dot = v
+                .iter()
+                .enumerate()
+                .map(|(cell, &n)| usize::from(n) * usize::from(arrows.arrow(cell).0));
+            dot.sum::<usize>() % usize::from(self.modulus) == 0
+        })
+    }
+}
+
+/// Row reduces `m` modulo `modulus`, which needs to be 2 or 3, with pivots
+/// among its first `columns` columns. Returns the pivot column of each of the
+/// first `rank` rows, and the rank.
+const fn row_reduce<const C: usize>(
+    m: &mut [[u8; C]; 37],
+    modulus: u8,
+    columns: usize,
+) -> ([usize; 37], usize) {
+    let mut pivot_columns = [0; 37];
+    let mut rank = 0;
+    let mut c = 0;
+    while c < columns {
+        let mut r = rank;
+        while r < 37 && m[r][c] == 0 {
+            r += 1;
+        }
+        if r < 37 {
+            let row = m[r];
+            m[r] = m[rank];
+            m[rank] = row;
+            // Modulo 2 and 3, every number but 0 is its own inverse
+            let inverse = m[rank][c];
+            let mut k = 0;
+            while k < C {
+                m[rank][k] = m[rank][k] * inverse % modulus;
+                k += 1;
+            }
+            let mut i = 0;
+            while i < 37 {
+                let factor = m[i][c];
+                if i != rank && factor != 0 {
+                    let mut k = 0;
+                    while k < C {
+                        m[i][k] = (m[i][k] + (modulus - factor) * m[rank][k]) % modulus;
+                        k += 1;
+                    }
+                }
+                i += 1;
+            }
+            pivot_columns[rank] = c;
+            rank += 1;
+        }
+        c += 1;
+    }
+    (pivot_columns, rank)
+}
+
+/// A basis of the kernel of the poke matrix modulo `modulus`, which needs to
+/// be 2 or 3, and its dimension.
+const fn kernel(modulus: u8) -> ([[u8; 37]; 37], usize) {
+    // Row `i`, column `j` says how many times poking cell `j` turns cell `i`
+    let mut m = [[0; 37]; 37];
+    let mut i = 0;
+    while i < 37 {
+        let mut j = 0;
+        while j < 37 {
+            m[i][j] = ((POKE_MASKS[j] >> (3 * i)) & 1) as u8;
+            j += 1;
         }
-        poke_counts
+        i += 1;
     }
 
-    pub fn solve(self) -> Hex<usize> {
-        let mut boards = Vec::with_capacity(12);
-        let mut board = self;
-        for flipped in [false, true] {
-            for rotation in 0..6 {
-                boards.push((flipped, rotation, board.clone()));
-                board.0.rotate_60_cw();
-            }
-            board.0.flip_horizontally();
-        }
-
-        boards
-            .into_iter()
-            .map(|(flipped, rotation, board)| {
-                let mut poke_counts = board.solve_this_orientation();
-                for _ in 0..(6i64 - rotation).rem_euclid(6) {
-                    poke_counts.rotate_60_cw();
+    let (pivot_columns, rank) = row_reduce(&mut m, modulus, 37);
+    let mut is_pivot = [false; 37];
+    let mut r = 0;
+    while r < rank {
+        is_pivot[pivot_columns[r]] = true;
+        r += 1;
+    }
+
+    // Poking a free column once, and every pivot column enough to cancel it
+    let mut basis = [[0; 37]; 37];
+    let mut dimension = 0;
+    let mut free = 0;
+    while free < 37 {
+        if !is_pivot[free] {
+            basis[dimension][free] = 1;
+            let mut r = 0;
+            while r < rank {
+                basis[dimension][pivot_columns[r]] = (modulus - m[r][free]) % modulus;
+                r += 1;
+            }
+            dimension += 1;
+        }
+        free += 1;
+    }
+    (basis, dimension)
+}
+
+const KERNEL_MOD_2: ([[u8; 37]; 37], usize) = kernel(2);
+const KERNEL_MOD_3: ([[u8; 37]; 37], usize) = kernel(3);
+
+/// Modulo 2 and modulo 3. A plan modulo 6 is one of each at once, so
+/// `KERNELS[0].size() * KERNELS[1].size()` plans solve every solvable board.
+pub const KERNELS: [Kernel; 2] = [
+    Kernel {
+        modulus: 2,
+        basis: KERNEL_MOD_2.0.split_at(KERNEL_MOD_2.1).0,
+    },
+    Kernel {
+        modulus: 3,
+        basis: KERNEL_MOD_3.0.split_at(KERNEL_MOD_3.1).0,
+    },
+];
+
+/// Every plan that solves a board, modulo 6. See [`PackedBoard::solutions`].
+#[derive(Debug, Clone)]
+pub struct Solutions {
+    solution: PackedPlan,
+    next: usize,
+    len: usize,
+}
+
+impl Iterator for Solutions {
+    type Item = PackedPlan;
+
+    fn next(&mut self) -> Option<PackedPlan> {
+        if self.next == self.len {
+            return None;
+        }
+        // The digits of `next`, in the bases of the kernels, say how many
+        // times to add each basis plan. Modulo 6, 3 is 1 modulo 2 and 0
+        // modulo 3, and 4 the other way around.
+        let mut plan = self.solution;
+        let mut digits = self.next;
+        for (kernel, factor) in KERNELS.iter().zip([3, 4]) {
+            let modulus = usize::from(kernel.modulus);
+            for v in kernel.basis {
+                let digit = digits % modulus;
+                digits /= modulus;
+                for (cell, &n) in v.iter().enumerate() {
+                    plan.add(cell, factor * digit * usize::from(n));
+                }
+            }
+        }
+        self.next += 1;
+        Some(plan)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.len - self.next;
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for Solutions {}
+
+/// A board with some arrows unknown, e.g. because something covers them on
+/// screen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialBoard(Hex<Option<Arrow>>);
+
+/// Pokes for a [`PartialBoard`]. See [`PartialBoard::solve`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PartialPlan {
+    pub pokes: Hex<usize>,
+    /// The unknown cells that the known ones don't determine. The pokes leave
+    /// them turned some unknown way, so they need to be observed and solved
+    /// after. All `false` if the pokes solve the whole board.
+    pub needed: Hex<bool>,
+}
+
+impl From<&Board> for PartialBoard {
+    fn from(board: &Board) -> PartialBoard {
+        PartialBoard(Hex::from_fn(|x, y| board.0.at(x, y).copied()))
+    }
+}
+
+impl PartialBoard {
+    pub fn new(arrows: Hex<Option<Arrow>>) -> PartialBoard {
+        PartialBoard(arrows)
+    }
+
+    pub fn arrows(&self) -> &Hex<Option<Arrow>> {
+        &self.0
+    }
+
+    /// Plans pokes that turn up every known arrow, and every unknown one that
+    /// the known ones determine. `None` if no solvable board has the known
+    /// arrows, so that some of them must have been misdetected.
+    pub fn solve(&self) -> Option<PartialPlan> {
+        let (board, needed) = self.complete()?;
+        let mut needed = needed.into_iter();
+        Some(PartialPlan {
+            pokes: board.solve().into(),
+            needed: Hex::from_fn(|_, _| needed.next().unwrap()),
+        })
+    }
+
+    /// Fills in the unknown arrows, and says which cells got an arbitrary
+    /// arrow because the known ones don't determine it.
+    ///
+    /// Boards from the game are solvable, so for every plan of [`KERNELS`],
+    /// the arrows add up to 0 when multiplied by how many times the plan pokes
+    /// each cell. That's a system of equations for the unknown arrows, modulo
+    /// 2 and modulo 3. Every cell but the center shows up in it, so a few
+    /// unknown arrows can usually be determined.
+    fn complete(&self) -> Option<(PackedBoard, [bool; 37])> {
+        let mut arrows = [None; 37];
+        for (slot, (&a, _)) in arrows.iter_mut().zip(self.0.enumerate()) {
+            *slot = a;
+        }
+        let mut unknown = [0; 37];
+        let mut unknowns = 0;
+        for (cell, a) in arrows.iter().enumerate() {
+            if a.is_none() {
+                unknown[unknowns] = cell;
+                unknowns += 1;
+            }
+        }
+
+        let mut residues = [[0; 37]; 2];
+        let mut determined = [[false; 37]; 2];
+        for (k, kernel) in KERNELS.iter().enumerate() {
+            let modulus = kernel.modulus;
+            // One row per basis plan, with a column per unknown arrow and the
+            // sum of the known ones moved to the right hand side
+            let mut m = [[0; 38]; 37];
+            for (row, v) in m.iter_mut().zip(kernel.basis) {
+                for (column, &cell) in unknown[..unknowns].iter().enumerate() {
+                    row[column] = v[cell];
                 }
-                if flipped {
-                    poke_counts.flip_horizontally();
+                let known = arrows
+                    .iter()
+                    .zip(v)
+                    .filter_map(|(a, &n)| Some(usize::from((*a)?.0) * usize::from(n)))
+                    .sum::<usize>();
+                let modulus = usize::from(modulus);
+                row[37] = ((modulus - known % modulus) % modulus) as u8;
+            }
+            let (pivot_columns, rank) = row_reduce(&mut m, modulus, unknowns);
+            if m[rank..].iter().any(|row| row[37] != 0) {
+                return None;
+            }
+            for (row, &column) in m.iter().zip(&pivot_columns[..rank]) {
+                let cell = unknown[column];
+                residues[k][cell] = row[37];
+                determined[k][cell] = (0..unknowns).all(|c| c == column || row[c] == 0);
+            }
+        }
+
+        let mut bits = 0;
+        let mut needed = [false; 37];
+        for (cell, a) in arrows.into_iter().enumerate() {
+            let arrow = match a {
+                Some(a) => usize::from(a.0 % 6),
+                None => {
+                    needed[cell] = !(determined[0][cell] && determined[1][cell]);
+                    // 3 is 1 modulo 2 and 0 modulo 3, and 4 the other way around
+                    usize::from(3 * residues[0][cell] + 4 * residues[1][cell]) % 6
                 }
-                poke_counts
-            })
-            .min_by_key(|p| p.enumerate().map(|(&n, _)| n).sum::<usize>())
-            .unwrap()
+            };
+            bits |= (arrow as u128) << (3 * cell);
+        }
+        Some((PackedBoard(bits), needed))
+    }
+}
+
+/// Strategies for property tests of boards and plans.
+#[cfg(feature = "proptest")]
+pub mod arbitrary {
+    use proptest::{
+        arbitrary::{any, Arbitrary},
+        collection::vec,
+        strategy::{BoxedStrategy, Strategy},
+    };
+
+    use super::{Arrow, Board};
+    use crate::hex::Hex;
+
+    /// Up to `max` pokes for every cell.
+    pub fn pokes(max: usize) -> impl Strategy<Value = Hex<usize>> {
+        vec(0..=max, Hex::<usize>::POSITIONS.len()).prop_map(|counts| {
+            let mut counts = counts.into_iter();
+            Hex::from_fn(|_, _| counts.next().unwrap())
+        })
+    }
+
+    /// Boards that the game can show, which are the solved board scrambled by
+    /// up to 5 pokes for every cell.
+    pub fn solvable_board() -> impl Strategy<Value = Board> {
+        pokes(5).prop_map(|scramble| {
+            let mut board = Board::new(Hex::from_fn(|_, _| Arrow::UP));
+            board.poke_all(&scramble);
+            board
+        })
+    }
+
+    impl Arbitrary for Arrow {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Arrow>;
+
+        fn arbitrary_with(_: ()) -> Self::Strategy {
+            (0..6u8).prop_map(Arrow).boxed()
+        }
+    }
+
+    /// Any board, solvable or not.
+    impl Arbitrary for Board {
+        type Parameters = ();
+        type Strategy = BoxedStrategy<Board>;
+
+        fn arbitrary_with(_: ()) -> Self::Strategy {
+            vec(any::<Arrow>(), Hex::<Arrow>::POSITIONS.len())
+                .prop_map(|arrows| {
+                    let mut arrows = arrows.into_iter();
+                    Board::new(Hex::from_fn(|_, _| arrows.next().unwrap()))
+                })
+                .boxed()
+        }
     }
 }
@@ -1,12 +1,12 @@
-use std::{
+#[cfg(feature = "alloc")]
+use alloc::{borrow::Cow, string::String, vec::Vec};
+#[cfg(feature = "alloc")]
+use core::fmt::Write;
+use core::{
     array,
-    borrow::Cow,
-    fmt::Write,
     ops::{Index, IndexMut},
 };
 
-use itertools::Itertools;
-
 use self::positions::Position;
 
 const fn indices(positions: [Position; 37]) -> [usize; 37] {
@@ -19,6 +19,40 @@ const fn indices(positions: [Position; 37]) -> [usize; 37] {
     out
 }
 
+/// The index in `positions` of the position at `(x, y)`.
+const fn cell_at(positions: [Position; 37], x: usize, y: usize) -> usize {
+    let mut i = 0;
+    loop {
+        let (px, py) = positions[i].as_xy();
+        if px == x && py == y {
+            return i;
+        }
+        i += 1;
+    }
+}
+
+const fn cells_rotated_60(positions: [Position; 37], rotated: [Position; 37]) -> [usize; 37] {
+    let mut out = [0; 37];
+    let mut i = 0;
+    while i < 37 {
+        let (x, y) = rotated[i].as_xy();
+        out[i] = cell_at(positions, x, y);
+        i += 1;
+    }
+    out
+}
+
+const fn cells_flipped(positions: [Position; 37]) -> [usize; 37] {
+    let mut out = [0; 37];
+    let mut i = 0;
+    while i < 37 {
+        let (x, y) = positions[i].as_xy();
+        out[i] = cell_at(positions, y, x);
+        i += 1;
+    }
+    out
+}
+
 const fn position_to_index(positions: [Position; 37]) -> [[Option<usize>; 7]; 7] {
     let mut out = [[None; 7]; 7];
     let mut i = 0;
@@ -46,6 +80,14 @@ pub mod positions {
         }
     }
 
+    /// The name of the constant, like `C2`.
+    impl core::fmt::Display for Position {
+        fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+            let Position((x, y)) = *self;
+            write!(f, "{}{}", char::from(b'A' + y as u8), x)
+        }
+    }
+
     pub const A0: Position = Position((0, 0));
     pub const A1: Position = Position((1, 0));
     pub const A2: Position = Position((2, 0));
@@ -170,6 +212,20 @@ impl<T> Hex<T> {
     };
     const INDICES_ROTATED_60: [usize; 37] = indices(Self::POSITIONS_ROTATED_60);
 
+    /// Where [`Hex::rotate_60_cw`] moves every item from, as indices into
+    /// [`Hex::POSITIONS`]. Item `i` of the rotated hex was item
+    /// `CELLS_ROTATED_60[i]`.
+    pub const CELLS_ROTATED_60: [usize; 37] =
+        cells_rotated_60(Self::POSITIONS, Self::POSITIONS_ROTATED_60);
+    /// Like [`Hex::CELLS_ROTATED_60`], for [`Hex::flip_horizontally`].
+    pub const CELLS_FLIPPED: [usize; 37] = cells_flipped(Self::POSITIONS);
+
+    /// The index of `p` in [`Hex::POSITIONS`].
+    pub const fn cell(p: Position) -> usize {
+        let (x, y) = p.as_xy();
+        cell_at(Self::POSITIONS, x, y)
+    }
+
     pub fn from_fn<F>(mut f: F) -> Hex<T>
     where
         F: FnMut(usize, usize) -> T,
@@ -246,6 +302,7 @@ impl<T> Hex<T> {
         *self = flipped;
     }
 
+    #[cfg(feature = "alloc")]
     pub fn visualize<F>(&self, mut f: F) -> String
     where
         F: FnMut(&T) -> Cow<str>,
@@ -365,7 +422,7 @@ impl<T> Hex<T> {
                 Some(p) => f(&self[p]),
                 None => "".into(),
             })
-            .collect_vec();
+            .collect::<Vec<_>>();
         let longest = cows.iter().map(|c| c.chars().count()).max().unwrap_or(0);
 
         let mut buf = String::new();
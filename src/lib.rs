@@ -1,4 +1,21 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "device")]
 pub mod app;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "device")]
 pub mod device;
+#[cfg(feature = "solver")]
 pub mod expert;
+#[cfg(feature = "solver")]
 pub mod hex;
+#[cfg(feature = "device")]
+pub mod observer;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "device")]
+pub mod session;
@@ -1,25 +1,202 @@
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
 use anyhow::Context;
-use clap::Parser;
+use clap::{ArgAction, Parser};
+use log::{error, LevelFilter};
 use solve_arrow_puzzle::{
-    app::play,
-    device::{ScrcpyDevice, Transform, Vec2},
+    app::{play, Device},
+    device::{
+        Orientation, Profile, ReplayDevice, ScrcpyDevice, ScreenTemplates, ScreencapDevice,
+        Transform, Vec2, VideoBackend,
+    },
+    expert::Board,
+    observer::{LiveStats, LogObserver},
+    session::SessionRecorder,
 };
 
 #[derive(Debug, Parser)]
-struct Args {}
+struct Args {
+    /// Decode the video with an ffmpeg child process even when the
+    /// in-process decoder is available
+    #[arg(long)]
+    ffmpeg: bool,
+    /// Take screenshots with `adb exec-out screencap` instead of streaming the
+    /// screen with scrcpy. Slower, but needs nothing but adb
+    #[arg(long)]
+    screencap: bool,
+    /// How far the screen is rotated clockwise from portrait. Picked from the
+    /// size of the frames if not given
+    #[arg(long, value_enum)]
+    orientation: Option<Orientation>,
+    /// Write a JSON summary of the run to this file when it ends, instead of
+    /// to stdout
+    #[arg(long)]
+    summary: Option<PathBuf>,
+    /// Record the session into this directory, to diagnose a failed run
+    /// later. See the `session` module for the format
+    #[arg(long)]
+    record: Option<PathBuf>,
+    /// Only record the lumas of every this many frames the player looks at,
+    /// since every frame takes a megabyte or two. The player skips frames
+    /// that come in while it's busy, and those are never recorded
+    #[arg(long, default_value_t = 1)]
+    record_every: u64,
+    /// Play back a recorded session instead of playing on a device, and fail
+    /// if the taps differ from the recorded ones
+    #[arg(long, conflicts_with_all = ["screencap", "ffmpeg"])]
+    replay: Option<PathBuf>,
+    /// Print every poke that solves this board, and the board after it,
+    /// instead of playing. The board is 37 digits for the arrows A0, A1, ...,
+    /// G6, and whitespace is ignored
+    #[arg(long, value_name = "ARROWS", conflicts_with_all = ["replay", "record", "summary"])]
+    explain: Option<Board>,
+    /// Read the templates that tell the screens apart from this JSON file,
+    /// to recognize popups and ads on top of the built-in ones. Screens it
+    /// leaves out keep the built-in templates
+    #[arg(long, value_name = "FILE")]
+    templates: Option<PathBuf>,
+    /// Log more. Once for stats, twice for every state
+    /// transition, three times for every frame. `RUST_LOG` takes precedence
+    #[arg(short, long, action = ArgAction::Count)]
+    verbose: u8,
+}
+
+/// Plays until Ctrl-C or a fatal error, then writes the summary either way.
+fn play_and_summarize<D>(
+    device: D,
+    stop: Arc<AtomicBool>,
+    recorder: Option<SessionRecorder>,
+    summary: Option<PathBuf>,
+) -> anyhow::Result<()>
+where
+    D: Device,
+{
+    {
+        let stop = stop.clone();
+        ctrlc::set_handler(move || stop.store(true, Ordering::Relaxed))
+            .context("set ctrl-c handler")?;
+    }
+
+    let mut live_stats = LiveStats::new(Instant::now());
+    let observer = (LogObserver, (&mut live_stats, recorder.clone()));
+    let played = play(device, observer, &stop).context("play");
+    let recorded = recorder
+        .map_or(Ok(()), |r| r.finish())
+        .context("record session");
+    let stats = &mut live_stats.stats;
+    stats.finish(Instant::now());
+    eprintln!();
+
+    let written = match summary {
+        Some(path) => File::create(&path)
+            .with_context(|| format!("create {}", path.display()))
+            .and_then(|f| serde_json::to_writer_pretty(f, stats).context("write summary")),
+        None => serde_json::to_writer_pretty(io::stdout(), stats)
+            .context("write summary")
+            .and_then(|()| writeln!(io::stdout()).context("write summary")),
+    };
+    played.and(recorded).and(written)
+}
+
+/// Prints the steps of solving `board`, to see how the solver works.
+fn explain(board: Board) -> io::Result<()> {
+    let mut out = io::stdout().lock();
+    writeln!(out, "{}", board)?;
+    if !board.is_solvable() {
+        writeln!(
+            out,
+            "\nno pokes solve this board, so the steps won't either"
+        )?;
+    }
+    for (i, step) in board.solve_steps().enumerate() {
+        writeln!(out, "\n{}. {} poke {}", i + 1, step.group, step.poke)?;
+        writeln!(out, "{}", step.board)?;
+    }
+    Ok(())
+}
 
 fn run(args: Args) -> anyhow::Result<()> {
-    let device = ScrcpyDevice::new(
-        1440,
-        3120,
-        Vec2::new(721.0, 2750.0),
-        2,
-        Transform::new(Vec2::new(721.5, 1178.5), Vec2::new(721.5, 2430.5)),
-        10001,
-        10002,
-    )
-    .context("create scrcpy server device")?;
-    play(device).context("play")
+    let level = match args.verbose {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    };
+    env_logger::Builder::new()
+        .filter_level(level)
+        .parse_default_env()
+        .init();
+
+    if let Some(board) = args.explain {
+        return explain(board).context("print steps");
+    }
+
+    let templates = match &args.templates {
+        Some(path) => {
+            let file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+            serde_json::from_reader(io::BufReader::new(file))
+                .with_context(|| format!("read templates from {}", path.display()))?
+        }
+        None => ScreenTemplates::default(),
+    };
+    let profile = Profile {
+        screen_width: 1440,
+        screen_height: 3120,
+        claim_button: Vec2::new(721.0, 2750.0),
+        transform: Transform::new(Vec2::new(721.5, 1178.5), Vec2::new(721.5, 2430.5)),
+        orientation: args.orientation,
+        templates,
+    };
+
+    let recorder = args
+        .record
+        .map(|dir| SessionRecorder::create(&dir, args.record_every))
+        .transpose()
+        .context("start recording")?;
+
+    if let Some(dir) = args.replay {
+        let mut device = ReplayDevice::new(profile, &dir).context("create replay device")?;
+        let stop = device.done();
+        let played = play_and_summarize(&mut device, stop, recorder, args.summary);
+        let replayed = device.finish().context("replay");
+        // Diverging usually makes playing fail too, so tell both
+        if let (Err(_), Err(err)) = (&played, &replayed) {
+            error!("{:#}", err);
+        }
+        played.and(replayed)?;
+        eprintln!("replay matched the recording");
+        return Ok(());
+    }
+
+    let stop = Arc::new(AtomicBool::new(false));
+    if args.screencap {
+        let mut device = ScreencapDevice::new(profile).context("create screencap device")?;
+        if let Some(recorder) = &recorder {
+            device.record_to(recorder.clone());
+        }
+        return play_and_summarize(device, stop, recorder, args.summary);
+    }
+
+    let video_backend = if args.ffmpeg {
+        VideoBackend::Ffmpeg
+    } else {
+        VideoBackend::default()
+    };
+    let mut device = ScrcpyDevice::new(profile, 2, video_backend, 10001, 10002)
+        .context("create scrcpy server device")?;
+    if let Some(recorder) = &recorder {
+        device.record_to(recorder.clone());
+    }
+    play_and_summarize(device, stop, recorder, args.summary)
 }
 
 fn main() {
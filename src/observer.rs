@@ -0,0 +1,474 @@
+use std::{
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+use log::{debug, info, log_enabled, trace, warn, Level};
+use serde::Serialize;
+
+use crate::{
+    app::{error_chain, Detection, Frame, Plan, PlayError, PlayerState, ScreenState},
+    expert::Board,
+    hex::Hex,
+};
+
+/// Where in the play loop an error came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorSource {
+    /// Detecting the screen failed. The device gets reconnected.
+    Detect,
+    /// Tapping failed. The device gets reconnected.
+    Tap,
+    /// Waiting for the next frame failed. The device gets reconnected.
+    Wait,
+    /// Reconnecting failed. It gets tried again until `play` gives up.
+    Reconnect,
+    /// The player gave up, which ends `play`.
+    Player,
+}
+
+/// Gets told what `play` sees and does. Every callback does nothing by
+/// default.
+#[allow(unused_variables)]
+pub trait PlayObserver {
+    /// Every frame, whatever it shows.
+    fn on_detection(&mut self, detection: &Detection) {}
+    /// Every frame that shows the expert board, solved or not.
+    fn on_board_detected(&mut self, frame: &Frame, board: &Board) {}
+    /// `elapsed` is how long the player was in `from`.
+    fn on_state_change(
+        &mut self,
+        now: Instant,
+        from: PlayerState,
+        to: PlayerState,
+        elapsed: Duration,
+    ) {
+    }
+    /// Right before poking the board. `again` if the board was poked before
+    /// but didn't come out solved, in which case [`Plan::resend`] tells
+    /// whether only the dropped pokes are sent again.
+    fn on_solve(&mut self, now: Instant, plan: &Plan, again: bool) {}
+    /// Right before tapping the claim button. `retry` if the last tap didn't
+    /// register.
+    fn on_claim(&mut self, now: Instant, retry: bool) {}
+    fn on_go_back(&mut self, now: Instant) {}
+    fn on_error(&mut self, now: Instant, source: ErrorSource, err: &PlayError) {}
+    fn on_reconnect(&mut self, now: Instant) {}
+}
+
+/// Ignores everything.
+impl PlayObserver for () {}
+
+impl<O> PlayObserver for &mut O
+where
+    O: PlayObserver + ?Sized,
+{
+    fn on_detection(&mut self, detection: &Detection) {
+        (**self).on_detection(detection)
+    }
+
+    fn on_board_detected(&mut self, frame: &Frame, board: &Board) {
+        (**self).on_board_detected(frame, board)
+    }
+
+    fn on_state_change(
+        &mut self,
+        now: Instant,
+        from: PlayerState,
+        to: PlayerState,
+        elapsed: Duration,
+    ) {
+        (**self).on_state_change(now, from, to, elapsed)
+    }
+
+    fn on_solve(&mut self, now: Instant, plan: &Plan, again: bool) {
+        (**self).on_solve(now, plan, again)
+    }
+
+    fn on_claim(&mut self, now: Instant, retry: bool) {
+        (**self).on_claim(now, retry)
+    }
+
+    fn on_go_back(&mut self, now: Instant) {
+        (**self).on_go_back(now)
+    }
+
+    fn on_error(&mut self, now: Instant, source: ErrorSource, err: &PlayError) {
+        (**self).on_error(now, source, err)
+    }
+
+    fn on_reconnect(&mut self, now: Instant) {
+        (**self).on_reconnect(now)
+    }
+}
+
+/// Tells the observer if there is one.
+impl<O> PlayObserver for Option<O>
+where
+    O: PlayObserver,
+{
+    fn on_detection(&mut self, detection: &Detection) {
+        if let Some(o) = self {
+            o.on_detection(detection)
+        }
+    }
+
+    fn on_board_detected(&mut self, frame: &Frame, board: &Board) {
+        if let Some(o) = self {
+            o.on_board_detected(frame, board)
+        }
+    }
+
+    fn on_state_change(
+        &mut self,
+        now: Instant,
+        from: PlayerState,
+        to: PlayerState,
+        elapsed: Duration,
+    ) {
+        if let Some(o) = self {
+            o.on_state_change(now, from, to, elapsed)
+        }
+    }
+
+    fn on_solve(&mut self, now: Instant, plan: &Plan, again: bool) {
+        if let Some(o) = self {
+            o.on_solve(now, plan, again)
+        }
+    }
+
+    fn on_claim(&mut self, now: Instant, retry: bool) {
+        if let Some(o) = self {
+            o.on_claim(now, retry)
+        }
+    }
+
+    fn on_go_back(&mut self, now: Instant) {
+        if let Some(o) = self {
+            o.on_go_back(now)
+        }
+    }
+
+    fn on_error(&mut self, now: Instant, source: ErrorSource, err: &PlayError) {
+        if let Some(o) = self {
+            o.on_error(now, source, err)
+        }
+    }
+
+    fn on_reconnect(&mut self, now: Instant) {
+        if let Some(o) = self {
+            o.on_reconnect(now)
+        }
+    }
+}
+
+/// Tells both, first `A` and then `B`.
+impl<A, B> PlayObserver for (A, B)
+where
+    A: PlayObserver,
+    B: PlayObserver,
+{
+    fn on_detection(&mut self, detection: &Detection) {
+        self.0.on_detection(detection);
+        self.1.on_detection(detection);
+    }
+
+    fn on_board_detected(&mut self, frame: &Frame, board: &Board) {
+        self.0.on_board_detected(frame, board);
+        self.1.on_board_detected(frame, board);
+    }
+
+    fn on_state_change(
+        &mut self,
+        now: Instant,
+        from: PlayerState,
+        to: PlayerState,
+        elapsed: Duration,
+    ) {
+        self.0.on_state_change(now, from, to, elapsed);
+        self.1.on_state_change(now, from, to, elapsed);
+    }
+
+    fn on_solve(&mut self, now: Instant, plan: &Plan, again: bool) {
+        self.0.on_solve(now, plan, again);
+        self.1.on_solve(now, plan, again);
+    }
+
+    fn on_claim(&mut self, now: Instant, retry: bool) {
+        self.0.on_claim(now, retry);
+        self.1.on_claim(now, retry);
+    }
+
+    fn on_go_back(&mut self, now: Instant) {
+        self.0.on_go_back(now);
+        self.1.on_go_back(now);
+    }
+
+    fn on_error(&mut self, now: Instant, source: ErrorSource, err: &PlayError) {
+        self.0.on_error(now, source, err);
+        self.1.on_error(now, source, err);
+    }
+
+    fn on_reconnect(&mut self, now: Instant) {
+        self.0.on_reconnect(now);
+        self.1.on_reconnect(now);
+    }
+}
+
+/// Logs every event as `key=value` pairs. Frames are logged at trace level,
+/// the player's states and actions at debug, and errors as warnings.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogObserver;
+
+impl PlayObserver for LogObserver {
+    fn on_detection(&mut self, detection: &Detection) {
+        let Detection { frame, screen } = detection;
+        trace!(
+            "detection frame={} frame_age={:?} screen={}",
+            frame.sequence,
+            frame.captured_at.elapsed(),
+            screen,
+        );
+    }
+
+    fn on_board_detected(&mut self, frame: &Frame, board: &Board) {
+        if !board.is_solvable() {
+            warn!(
+                "unsolvable_board frame={} board=\n{}",
+                frame.sequence, board
+            );
+        }
+    }
+
+    fn on_state_change(
+        &mut self,
+        _now: Instant,
+        from: PlayerState,
+        to: PlayerState,
+        elapsed: Duration,
+    ) {
+        debug!(
+            "transition from={:?} to={:?} elapsed={:?}",
+            from, to, elapsed
+        );
+    }
+
+    fn on_solve(&mut self, _now: Instant, plan: &Plan, again: bool) {
+        let pokes: usize = plan.pokes.enumerate().map(|(&n, _)| n).sum();
+        debug!(
+            "solve pokes={} again={} resend={} board=\n{}",
+            pokes, again, plan.resend, plan.board
+        );
+    }
+
+    fn on_claim(&mut self, _now: Instant, retry: bool) {
+        debug!("claim retry={}", retry);
+    }
+
+    fn on_go_back(&mut self, _now: Instant) {
+        debug!("go_back");
+    }
+
+    fn on_error(&mut self, _now: Instant, source: ErrorSource, err: &PlayError) {
+        warn!("error source={:?} error={:?}", source, error_chain(err));
+    }
+
+    fn on_reconnect(&mut self, _now: Instant) {
+        info!("reconnect");
+    }
+}
+
+/// What happened during a run of `play`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Stats {
+    pub run_time_secs: f64,
+    pub boards_solved: usize,
+    pub claims: usize,
+    /// Pokes on the board and taps on the claim button.
+    pub taps_sent: usize,
+    /// How many times pokes went missing and only those were sent again.
+    pub re_solves: usize,
+    /// How many times a board didn't come out solved in a way missing pokes
+    /// don't explain, so it got solved again from scratch.
+    pub full_re_solves: usize,
+    /// How many pokes were sent to each cell, in the order of
+    /// [`Hex::POSITIONS`], including the ones sent again and the ones of full
+    /// re-solves.
+    pub pokes_sent: Vec<usize>,
+    /// How many of the pokes sent to each cell went missing, as far as the
+    /// re-solves tell.
+    pub pokes_dropped: Vec<usize>,
+    /// Frames that showed no usable board nor any other known screen, and
+    /// detections that failed altogether.
+    pub detection_failures: usize,
+    pub reconnects: usize,
+    /// From first poking each board until claiming its rewards.
+    pub solve_times_secs: Vec<f64>,
+    pub total_solve_time_secs: f64,
+    #[serde(skip)]
+    started_at: Instant,
+    #[serde(skip)]
+    board_started_at: Option<Instant>,
+    /// The sequence of the last frame counted as a detection failure, which
+    /// may get detected again while the screen doesn't change.
+    #[serde(skip)]
+    last_failed_frame: Option<u64>,
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.0}s: {} solved, {} claims, {} taps, {} re-solves, {} full re-solves, {} detection failures, {} reconnects",
+            self.run_time_secs,
+            self.boards_solved,
+            self.claims,
+            self.taps_sent,
+            self.re_solves,
+            self.full_re_solves,
+            self.detection_failures,
+            self.reconnects,
+        )?;
+        if !self.solve_times_secs.is_empty() {
+            let mean = self.total_solve_time_secs / self.solve_times_secs.len() as f64;
+            write!(f, ", {:.1}s per board", mean)?;
+        }
+        Ok(())
+    }
+}
+
+impl Stats {
+    pub fn new(started_at: Instant) -> Stats {
+        Stats {
+            run_time_secs: 0.0,
+            boards_solved: 0,
+            claims: 0,
+            taps_sent: 0,
+            re_solves: 0,
+            full_re_solves: 0,
+            pokes_sent: vec![0; Hex::<usize>::POSITIONS.len()],
+            pokes_dropped: vec![0; Hex::<usize>::POSITIONS.len()],
+            detection_failures: 0,
+            reconnects: 0,
+            solve_times_secs: Vec::new(),
+            total_solve_time_secs: 0.0,
+            started_at,
+            board_started_at: None,
+            last_failed_frame: None,
+        }
+    }
+
+    /// Updates `run_time_secs`, which is otherwise only kept current while
+    /// `play` runs.
+    pub fn finish(&mut self, now: Instant) {
+        self.run_time_secs = now.saturating_duration_since(self.started_at).as_secs_f64();
+    }
+}
+
+impl PlayObserver for Stats {
+    fn on_detection(&mut self, detection: &Detection) {
+        let usable = match &detection.screen {
+            ScreenState::ExpertBoard { board, .. } => board.is_solvable(),
+            ScreenState::Unknown => false,
+            _ => true,
+        };
+        let sequence = detection.frame.sequence;
+        if !usable && self.last_failed_frame != Some(sequence) {
+            self.detection_failures += 1;
+            self.last_failed_frame = Some(sequence);
+        }
+    }
+
+    fn on_solve(&mut self, now: Instant, plan: &Plan, again: bool) {
+        match (again, plan.resend) {
+            (false, _) => self.board_started_at = Some(now),
+            (true, true) => self.re_solves += 1,
+            (true, false) => self.full_re_solves += 1,
+        }
+        for (&n, p) in plan.pokes.enumerate() {
+            let cell = Hex::<usize>::cell(p);
+            self.pokes_sent[cell] += n;
+            if plan.resend {
+                self.pokes_dropped[cell] += n;
+            }
+        }
+        self.taps_sent += plan.pokes.enumerate().map(|(&n, _)| n).sum::<usize>();
+        self.finish(now);
+    }
+
+    fn on_claim(&mut self, now: Instant, retry: bool) {
+        self.taps_sent += 1;
+        if !retry {
+            self.claims += 1;
+            if let Some(started_at) = self.board_started_at.take() {
+                let solve_time = now.saturating_duration_since(started_at).as_secs_f64();
+                self.boards_solved += 1;
+                self.solve_times_secs.push(solve_time);
+                self.total_solve_time_secs += solve_time;
+            }
+        }
+        self.finish(now);
+    }
+
+    fn on_error(&mut self, now: Instant, source: ErrorSource, _err: &PlayError) {
+        if source == ErrorSource::Detect {
+            self.detection_failures += 1;
+        }
+        self.finish(now);
+    }
+
+    fn on_reconnect(&mut self, now: Instant) {
+        self.reconnects += 1;
+        self.finish(now);
+    }
+}
+
+/// Keeps `Stats` and reports them whenever they change, as a log line if info
+/// logs are on and as a line that keeps getting overwritten on stderr
+/// otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LiveStats {
+    pub stats: Stats,
+}
+
+impl LiveStats {
+    pub fn new(started_at: Instant) -> LiveStats {
+        LiveStats {
+            stats: Stats::new(started_at),
+        }
+    }
+
+    fn report(&self) {
+        if log_enabled!(Level::Info) {
+            info!("{}", self.stats);
+        } else {
+            eprint!("\r{}", self.stats);
+        }
+    }
+}
+
+impl PlayObserver for LiveStats {
+    fn on_detection(&mut self, detection: &Detection) {
+        self.stats.on_detection(detection);
+    }
+
+    fn on_solve(&mut self, now: Instant, plan: &Plan, again: bool) {
+        self.stats.on_solve(now, plan, again);
+        self.report();
+    }
+
+    fn on_claim(&mut self, now: Instant, retry: bool) {
+        self.stats.on_claim(now, retry);
+        self.report();
+    }
+
+    fn on_error(&mut self, now: Instant, source: ErrorSource, err: &PlayError) {
+        self.stats.on_error(now, source, err);
+        self.report();
+    }
+
+    fn on_reconnect(&mut self, now: Instant) {
+        self.stats.on_reconnect(now);
+        self.report();
+    }
+}
@@ -0,0 +1,140 @@
+//! The `solve_arrow_puzzle` Python extension module, for analysis notebooks
+//! that would otherwise shell out to the binary.
+//!
+//! Boards and tap plans cross over as nested lists, one list per row of the
+//! board from `A` to `G`, of 4, 5, 6, 7, 6, 5 and 4 cells. That's the order
+//! of [`Hex::POSITIONS`], so a flat list or a string of 37 digits works as a
+//! board too.
+//!
+//! ```python
+//! from solve_arrow_puzzle import Board
+//!
+//! board = Board("0123 01234 012345 0123450 123450 23450 3450")
+//! taps = board.solve()  # [[4 counts], [5 counts], ...]
+//! print(board)
+//! ```
+//!
+//! Build it with
+//! `cargo rustc --release --lib --no-default-features --features python --crate-type cdylib`
+//! and import `target/release/libsolve_arrow_puzzle.so` as
+//! `solve_arrow_puzzle.so`. `tests/python_smoke_test.py` does just that.
+
+use pyo3::{exceptions::PyValueError, prelude::*};
+
+use crate::{
+    expert::{Arrow, Board},
+    hex::Hex,
+};
+
+/// How many cells each row of the board has, from `A` to `G`.
+const ROW_LENGTHS: [usize; 7] = [4, 5, 6, 7, 6, 5, 4];
+
+fn to_rows<T, U, F>(hex: &Hex<T>, mut f: F) -> Vec<Vec<U>>
+where
+    U: Clone,
+    F: FnMut(&T) -> U,
+{
+    let mut rows = vec![Vec::new(); ROW_LENGTHS.len()];
+    for (t, p) in hex.enumerate() {
+        rows[p.as_xy().1].push(f(t));
+    }
+    rows
+}
+
+/// Ignores whitespace, so that rows can be spaced apart.
+fn digits_from_str(s: &str) -> PyResult<Vec<u8>> {
+    s.chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| match c.to_digit(10) {
+            Some(d) => Ok(d as u8),
+            None => Err(PyValueError::new_err(format!(
+                "want digits, but got {:?}",
+                c
+            ))),
+        })
+        .collect()
+}
+
+fn digits_from_rows(rows: Vec<Vec<u8>>) -> PyResult<Vec<u8>> {
+    let lengths = rows.iter().map(Vec::len).collect::<Vec<_>>();
+    if lengths != ROW_LENGTHS {
+        return Err(PyValueError::new_err(format!(
+            "want rows of {:?} cells, but got {:?}",
+            ROW_LENGTHS, lengths
+        )));
+    }
+    Ok(rows.into_iter().flatten().collect())
+}
+
+fn board_from_digits(digits: Vec<u8>) -> PyResult<Board> {
+    let len = Hex::<Arrow>::POSITIONS.len();
+    if digits.len() != len {
+        return Err(PyValueError::new_err(format!(
+            "want {} arrows, but got {}",
+            len,
+            digits.len()
+        )));
+    }
+    let arrows = digits
+        .into_iter()
+        .map(Arrow::try_from)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| PyValueError::new_err(err.to_string()))?;
+    let mut arrows = arrows.into_iter();
+    Ok(Board::new(Hex::from_fn(|_, _| arrows.next().unwrap())))
+}
+
+/// A board of the expert puzzle. Every arrow is a number of clockwise turns
+/// from pointing up, within [0, 6).
+#[pyclass(name = "Board", module = "solve_arrow_puzzle", frozen, eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PyBoard(Board);
+
+#[pymethods]
+impl PyBoard {
+    /// Takes the arrows as rows, as a flat list, or as a string of digits.
+    #[new]
+    fn new(arrows: &Bound<'_, PyAny>) -> PyResult<PyBoard> {
+        let digits = if let Ok(s) = arrows.extract::<String>() {
+            digits_from_str(&s)?
+        } else if let Ok(rows) = arrows.extract::<Vec<Vec<u8>>>() {
+            digits_from_rows(rows)?
+        } else {
+            arrows.extract::<Vec<u8>>()?
+        };
+        board_from_digits(digits).map(PyBoard)
+    }
+
+    /// The arrows as rows.
+    fn arrows(&self) -> Vec<Vec<usize>> {
+        to_rows(self.0.arrows(), |a| a.0.into())
+    }
+
+    fn is_solved(&self) -> bool {
+        self.0.is_solved()
+    }
+
+    /// How many times to tap every cell, as rows.
+    fn solve(&self) -> Vec<Vec<usize>> {
+        to_rows(&self.0.clone().solve(), |&n| n)
+    }
+
+    fn __str__(&self) -> String {
+        self.0.to_string()
+    }
+
+    fn __repr__(&self) -> String {
+        let digits: String = self
+            .0
+            .arrows()
+            .enumerate()
+            .map(|(a, _)| a.to_string())
+            .collect();
+        format!("Board({:?})", digits)
+    }
+}
+
+#[pymodule]
+fn solve_arrow_puzzle(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyBoard>()
+}
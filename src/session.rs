@@ -0,0 +1,418 @@
+//! Recordings of `play` sessions, for diagnosing failed runs without the
+//! phone.
+//!
+//! A session is a directory:
+//!
+//! - `session.json` holds [`SessionMeta`].
+//! - `events.jsonl` holds one [`Event`] per line, in the order they happened,
+//!   as JSON objects tagged by `"type"`. Every event has a `t`, the seconds
+//!   since the recording started.
+//! - `frames/00000000.y`, `frames/00000001.y`, ... hold the lumas of recorded
+//!   frames, one byte per pixel, row by row, `width` by `height` as given by
+//!   the `frame` event that names the file.
+//!
+//! Only the frames the player looked at are recorded. The device may receive
+//! more while the player is busy, so the sequences of `frame` events can
+//! skip some.
+//!
+//! Boards are written as 37 digits, one arrow per cell in the order of
+//! [`Hex::POSITIONS`], and so are poke counts. Control bytes are the messages
+//! sent to the scrcpy server, written as lowercase hex.
+
+use std::{
+    fmt::Write as _,
+    fs::{create_dir_all, File},
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    app::{error_chain, Detection, Frame, Plan, PlayError, PlayerState, ScreenState},
+    expert::Board,
+    hex::Hex,
+    observer::{ErrorSource, PlayObserver},
+};
+
+pub const SESSION_FORMAT_VERSION: u32 = 1;
+
+/// Why a session couldn't be recorded or read back.
+#[derive(Debug, Error)]
+pub enum SessionError {
+    #[error("failed to {0}")]
+    Io(String, #[source] io::Error),
+    #[error("failed to {0}")]
+    Json(String, #[source] serde_json::Error),
+    #[error("want session format version {}, but got {}", SESSION_FORMAT_VERSION, .0)]
+    Version(u32),
+    #[error("no frames recorded")]
+    NoFrames,
+    #[error("failed to take the lock for the recording")]
+    Poisoned,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionMeta {
+    pub version: u32,
+    /// Only every `frame_interval`th frame has its lumas recorded.
+    pub frame_interval: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// A frame the device looked at. `t` is when it was captured.
+    /// `size_divider` is how much smaller than the screen the frame is.
+    /// `lumas` is the path of the frame's file, relative to the session
+    /// directory, if it was recorded.
+    Frame {
+        t: f64,
+        sequence: u64,
+        width: usize,
+        height: usize,
+        size_divider: usize,
+        lumas: Option<String>,
+    },
+    /// What the frame with `sequence` showed. `board` is there for the
+    /// expert board.
+    Detection {
+        t: f64,
+        sequence: u64,
+        screen: String,
+        board: Option<String>,
+    },
+    /// `elapsed` is how many seconds the player was in `from`.
+    StateChange {
+        t: f64,
+        from: String,
+        to: String,
+        elapsed: f64,
+    },
+    Solve {
+        t: f64,
+        resend: bool,
+        pokes: String,
+    },
+    Claim {
+        t: f64,
+        retry: bool,
+    },
+    GoBack {
+        t: f64,
+    },
+    /// Bytes sent to the device to tap or press back.
+    Control {
+        t: f64,
+        bytes: String,
+    },
+    Error {
+        t: f64,
+        source: String,
+        message: String,
+    },
+    Reconnect {
+        t: f64,
+    },
+}
+
+pub fn board_to_digits(board: &Board) -> String {
+    board
+        .arrows()
+        .enumerate()
+        .map(|(a, _)| a.to_string())
+        .collect()
+}
+
+pub fn pokes_to_digits(pokes: &Hex<usize>) -> String {
+    pokes.enumerate().map(|(n, _)| n.to_string()).collect()
+}
+
+pub fn bytes_to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut s, b| {
+        let _ = write!(s, "{:02x}", b);
+        s
+    })
+}
+
+impl Event {
+    pub fn t(&self) -> f64 {
+        match *self {
+            Event::Frame { t, .. }
+            | Event::Detection { t, .. }
+            | Event::StateChange { t, .. }
+            | Event::Solve { t, .. }
+            | Event::Claim { t, .. }
+            | Event::GoBack { t }
+            | Event::Control { t, .. }
+            | Event::Error { t, .. }
+            | Event::Reconnect { t } => t,
+        }
+    }
+}
+
+/// Reads back a session written by [`SessionRecorder`].
+pub fn read_session(dir: &Path) -> Result<(SessionMeta, Vec<Event>), SessionError> {
+    let meta_file = File::open(dir.join("session.json"))
+        .map_err(|err| SessionError::Io("open session.json".into(), err))?;
+    let meta: SessionMeta = serde_json::from_reader(BufReader::new(meta_file))
+        .map_err(|err| SessionError::Json("read session.json".into(), err))?;
+    if meta.version != SESSION_FORMAT_VERSION {
+        return Err(SessionError::Version(meta.version));
+    }
+
+    let events_file = File::open(dir.join("events.jsonl"))
+        .map_err(|err| SessionError::Io("open events.jsonl".into(), err))?;
+    let events = BufReader::new(events_file)
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let line = line.map_err(|err| SessionError::Io("read events.jsonl".into(), err))?;
+            serde_json::from_str(&line)
+                .map_err(|err| SessionError::Json(format!("parse event on line {}", i + 1), err))
+        })
+        .collect::<Result<Vec<Event>, SessionError>>()?;
+    Ok((meta, events))
+}
+
+#[derive(Debug)]
+struct Recording {
+    dir: PathBuf,
+    events: BufWriter<File>,
+    started_at: Instant,
+    frame_interval: u64,
+    frames_seen: u64,
+    frames_recorded: u64,
+    /// Frames get looked at more than once while the screen doesn't change.
+    last_frame: Option<Frame>,
+    /// The first error while recording. Recording goes on without the events
+    /// that failed.
+    error: Option<SessionError>,
+}
+
+impl Recording {
+    fn t(&self, instant: Instant) -> f64 {
+        instant
+            .saturating_duration_since(self.started_at)
+            .as_secs_f64()
+    }
+
+    fn write_event(&mut self, event: &Event) {
+        let written = serde_json::to_writer(&mut self.events, event)
+            .map_err(|err| SessionError::Json("serialize event".into(), err))
+            .and_then(|()| {
+                writeln!(self.events).map_err(|err| SessionError::Io("write event".into(), err))
+            });
+        self.keep_error(written);
+    }
+
+    fn keep_error(&mut self, result: Result<(), SessionError>) {
+        if let Err(err) = result {
+            self.error.get_or_insert(err);
+        }
+    }
+
+    fn write_frame(&mut self, lumas: &[u8]) -> Result<String, SessionError> {
+        let name = format!("frames/{:08}.y", self.frames_recorded);
+        let path = self.dir.join(&name);
+        File::create(&path)
+            .and_then(|mut f| f.write_all(lumas))
+            .map_err(|err| SessionError::Io(format!("write {}", path.display()), err))?;
+        self.frames_recorded += 1;
+        Ok(name)
+    }
+}
+
+/// Records a session into a directory. Clones record into the same session,
+/// so one can be given to the device, for frames and control bytes, and
+/// another one to `play` as an observer, for everything else.
+#[derive(Debug, Clone)]
+pub struct SessionRecorder(Arc<Mutex<Recording>>);
+
+impl SessionRecorder {
+    /// Starts recording into `dir`, which is created if it doesn't exist.
+    /// Records the lumas of every `frame_interval`th frame.
+    pub fn create(dir: &Path, frame_interval: u64) -> Result<SessionRecorder, SessionError> {
+        let frame_interval = frame_interval.max(1);
+        create_dir_all(dir.join("frames"))
+            .map_err(|err| SessionError::Io(format!("create {}", dir.display()), err))?;
+        let meta = SessionMeta {
+            version: SESSION_FORMAT_VERSION,
+            frame_interval,
+        };
+        let meta_file = File::create(dir.join("session.json"))
+            .map_err(|err| SessionError::Io("create session.json".into(), err))?;
+        serde_json::to_writer_pretty(meta_file, &meta)
+            .map_err(|err| SessionError::Json("write session.json".into(), err))?;
+        let events = File::create(dir.join("events.jsonl"))
+            .map_err(|err| SessionError::Io("create events.jsonl".into(), err))?;
+
+        Ok(SessionRecorder(Arc::new(Mutex::new(Recording {
+            dir: dir.to_owned(),
+            events: BufWriter::new(events),
+            started_at: Instant::now(),
+            frame_interval,
+            frames_seen: 0,
+            frames_recorded: 0,
+            last_frame: None,
+            error: None,
+        }))))
+    }
+
+    fn with<F>(&self, f: F)
+    where
+        F: FnOnce(&mut Recording),
+    {
+        let mut recording = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        f(&mut recording)
+    }
+
+    /// Whether [`SessionRecorder::record_frame`] would write the lumas of
+    /// `frame`, so that they only need to be copied out of the decoder when
+    /// they do.
+    pub fn wants_lumas(&self, frame: &Frame) -> bool {
+        let recording = self.0.lock().unwrap_or_else(|err| err.into_inner());
+        recording.last_frame != Some(*frame)
+            && recording
+                .frames_seen
+                .is_multiple_of(recording.frame_interval)
+    }
+
+    /// Records a frame the first time the device looks at it, along with its
+    /// `lumas` if it's one of every `frame_interval` frames.
+    pub fn record_frame(
+        &self,
+        frame: &Frame,
+        width: usize,
+        height: usize,
+        size_divider: usize,
+        lumas: Option<&[u8]>,
+    ) {
+        self.with(|r| {
+            if r.last_frame == Some(*frame) {
+                return;
+            }
+            r.last_frame = Some(*frame);
+            let lumas = match lumas {
+                Some(lumas) if r.frames_seen.is_multiple_of(r.frame_interval) => {
+                    let written = r.write_frame(lumas);
+                    written.map_err(|err| r.keep_error(Err(err))).ok()
+                }
+                _ => None,
+            };
+            r.frames_seen += 1;
+            let event = Event::Frame {
+                t: r.t(frame.captured_at),
+                sequence: frame.sequence,
+                width,
+                height,
+                size_divider,
+                lumas,
+            };
+            r.write_event(&event);
+        })
+    }
+
+    pub fn record_control(&self, bytes: &[u8]) {
+        self.with(|r| {
+            let event = Event::Control {
+                t: r.t(Instant::now()),
+                bytes: bytes_to_hex(bytes),
+            };
+            r.write_event(&event);
+        })
+    }
+
+    /// Flushes what's left, and fails if anything couldn't be recorded.
+    pub fn finish(&self) -> Result<(), SessionError> {
+        let mut recording = self.0.lock().map_err(|_| SessionError::Poisoned)?;
+        let flushed = recording
+            .events
+            .flush()
+            .map_err(|err| SessionError::Io("flush events".into(), err));
+        recording.keep_error(flushed);
+        recording.error.take().map_or(Ok(()), Err)
+    }
+}
+
+impl PlayObserver for SessionRecorder {
+    fn on_detection(&mut self, detection: &Detection) {
+        self.with(|r| {
+            let board = match &detection.screen {
+                ScreenState::ExpertBoard { board, .. } => Some(board_to_digits(board)),
+                _ => None,
+            };
+            let event = Event::Detection {
+                t: r.t(Instant::now()),
+                sequence: detection.frame.sequence,
+                screen: detection.screen.to_string(),
+                board,
+            };
+            r.write_event(&event);
+        })
+    }
+
+    fn on_state_change(
+        &mut self,
+        now: Instant,
+        from: PlayerState,
+        to: PlayerState,
+        elapsed: Duration,
+    ) {
+        self.with(|r| {
+            let event = Event::StateChange {
+                t: r.t(now),
+                from: format!("{:?}", from),
+                to: format!("{:?}", to),
+                elapsed: elapsed.as_secs_f64(),
+            };
+            r.write_event(&event);
+        })
+    }
+
+    fn on_solve(&mut self, now: Instant, plan: &Plan, _again: bool) {
+        self.with(|r| {
+            let event = Event::Solve {
+                t: r.t(now),
+                resend: plan.resend,
+                pokes: pokes_to_digits(&plan.pokes),
+            };
+            r.write_event(&event);
+        })
+    }
+
+    fn on_claim(&mut self, now: Instant, retry: bool) {
+        self.with(|r| {
+            let event = Event::Claim { t: r.t(now), retry };
+            r.write_event(&event);
+        })
+    }
+
+    fn on_go_back(&mut self, now: Instant) {
+        self.with(|r| {
+            let event = Event::GoBack { t: r.t(now) };
+            r.write_event(&event);
+        })
+    }
+
+    fn on_error(&mut self, now: Instant, source: ErrorSource, err: &PlayError) {
+        self.with(|r| {
+            let event = Event::Error {
+                t: r.t(now),
+                source: format!("{:?}", source),
+                message: error_chain(err),
+            };
+            r.write_event(&event);
+        })
+    }
+
+    fn on_reconnect(&mut self, now: Instant) {
+        self.with(|r| {
+            let event = Event::Reconnect { t: r.t(now) };
+            r.write_event(&event);
+        })
+    }
+}
@@ -0,0 +1,71 @@
+#![cfg(all(feature = "capi", unix))]
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use solve_arrow_puzzle::capi::header;
+
+fn root() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Set `UPDATE_HEADER=1` to write the header instead.
+#[test]
+fn header_is_up_to_date() {
+    let path = root().join("include/solve_arrow_puzzle.h");
+    if env::var_os("UPDATE_HEADER").is_some() {
+        fs::write(&path, header()).unwrap();
+    }
+    let written = fs::read_to_string(&path).unwrap_or_default();
+    assert!(
+        written == header(),
+        "{} is out of date, run the tests again with UPDATE_HEADER=1",
+        path.display()
+    );
+}
+
+#[test]
+fn c_test_program_passes() {
+    // The target directory of this test is locked while it runs
+    let target_dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("capi");
+    let status = Command::new(env::var("CARGO").unwrap_or_else(|_| "cargo".into()))
+        .args(["rustc", "--quiet", "--lib", "--no-default-features"])
+        .args(["--features", "capi", "--crate-type", "cdylib"])
+        .arg("--target-dir")
+        .arg(&target_dir)
+        .current_dir(root())
+        .status()
+        .unwrap();
+    assert!(status.success(), "building the library failed: {}", status);
+
+    let lib_dir = target_dir.join("debug");
+    let program = target_dir.join("capi_test");
+    let status = Command::new(env::var("CC").unwrap_or_else(|_| "cc".into()))
+        .arg(root().join("tests/capi_test.c"))
+        .arg("-I")
+        .arg(root().join("include"))
+        .arg("-L")
+        .arg(&lib_dir)
+        .arg(format!("-Wl,-rpath,{}", lib_dir.display()))
+        .args(["-lsolve_arrow_puzzle", "-std=c99", "-Wall", "-Werror", "-o"])
+        .arg(&program)
+        .status()
+        .unwrap();
+    assert!(
+        status.success(),
+        "compiling the test program failed: {}",
+        status
+    );
+
+    // Cargo points the library path at its own target directory, which may
+    // have a stale build of the library
+    let status = Command::new(&program)
+        .env("LD_LIBRARY_PATH", &lib_dir)
+        .env("DYLD_LIBRARY_PATH", &lib_dir)
+        .status()
+        .unwrap();
+    assert!(status.success(), "the test program failed: {}", status);
+}
@@ -0,0 +1,107 @@
+/*
+ * Test program for the C API, built and run by `tests/capi.rs`. Exits with 0
+ * if every check passes.
+ */
+
+#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+
+#include "solve_arrow_puzzle.h"
+
+#define CELLS SOLVE_ARROW_PUZZLE_CELLS
+
+static int failures = 0;
+
+#define CHECK(cond)                                                        \
+    do {                                                                   \
+        if (!(cond)) {                                                     \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__,         \
+                    __LINE__, #cond);                                      \
+            failures++;                                                    \
+        }                                                                  \
+    } while (0)
+
+/* The position of every cell in the header's order, as row y from A to G and
+ * x within the row. */
+static int xs[CELLS], ys[CELLS];
+
+static void init_positions(void) {
+    int i = 0;
+    for (int y = 0; y < 7; y++) {
+        for (int x = y > 3 ? y - 3 : 0; x <= (y < 3 ? y + 3 : 6); x++) {
+            xs[i] = x;
+            ys[i] = y;
+            i++;
+        }
+    }
+}
+
+/* Turns the cell at `i` and its neighbors `n` times, like tapping it. */
+static void poke(uint8_t arrows[CELLS], int i, int n) {
+    static const int ds[7][2] = {{-1, -1}, {0, -1}, {-1, 0}, {0, 0},
+                                 {1, 0},   {0, 1},  {1, 1}};
+    for (int d = 0; d < 7; d++) {
+        for (int j = 0; j < CELLS; j++) {
+            if (xs[j] == xs[i] + ds[d][0] && ys[j] == ys[i] + ds[d][1]) {
+                arrows[j] = (uint8_t)((arrows[j] + n) % 6);
+            }
+        }
+    }
+}
+
+static int is_solved(const uint8_t arrows[CELLS]) {
+    for (int i = 0; i < CELLS; i++) {
+        if (arrows[i] != 0) {
+            return 0;
+        }
+    }
+    return 1;
+}
+
+int main(void) {
+    uint8_t arrows[CELLS], taps[CELLS];
+    init_positions();
+
+    memset(arrows, 0, sizeof arrows);
+    memset(taps, 0xff, sizeof taps);
+    CHECK(solve_arrow_puzzle_solve(arrows, taps) == SOLVE_ARROW_PUZZLE_OK);
+    for (int i = 0; i < CELLS; i++) {
+        CHECK(taps[i] == 0);
+    }
+
+    /* Boards scrambled from a solved one, since not every board is
+     * solvable */
+    uint32_t seed = 1;
+    for (int round = 0; round < 100; round++) {
+        memset(arrows, 0, sizeof arrows);
+        for (int i = 0; i < CELLS; i++) {
+            seed = seed * 1103515245u + 12345u;
+            poke(arrows, i, (int)((seed >> 16) % 6));
+        }
+        CHECK(solve_arrow_puzzle_solve(arrows, taps) == SOLVE_ARROW_PUZZLE_OK);
+        for (int i = 0; i < CELLS; i++) {
+            CHECK(taps[i] < 6);
+            poke(arrows, i, taps[i]);
+        }
+        CHECK(is_solved(arrows));
+    }
+
+    memset(arrows, 0, sizeof arrows);
+    arrows[CELLS - 1] = 6;
+    memset(taps, 0xff, sizeof taps);
+    CHECK(solve_arrow_puzzle_solve(arrows, taps) ==
+          SOLVE_ARROW_PUZZLE_ARROW_OUT_OF_RANGE);
+    for (int i = 0; i < CELLS; i++) {
+        CHECK(taps[i] == 0xff);
+    }
+
+    CHECK(solve_arrow_puzzle_solve(NULL, taps) == SOLVE_ARROW_PUZZLE_NULL_POINTER);
+    CHECK(solve_arrow_puzzle_solve(arrows, NULL) == SOLVE_ARROW_PUZZLE_NULL_POINTER);
+
+    if (failures > 0) {
+        fprintf(stderr, "%d checks failed\n", failures);
+        return 1;
+    }
+    return 0;
+}
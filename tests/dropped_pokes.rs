@@ -0,0 +1,33 @@
+#![cfg(feature = "proptest")]
+
+use proptest::prelude::*;
+use solve_arrow_puzzle::expert::arbitrary::{pokes, solvable_board};
+
+proptest! {
+    // Every case goes through all the solutions of a board
+    #![proptest_config(ProptestConfig::with_cases(200))]
+
+    #[test]
+    fn dropped_pokes_explain_any_board_a_plan_leaves(
+        board in solvable_board(),
+        registered in pokes(5),
+    ) {
+        let planned = board.clone().solve();
+        let mut registered = registered;
+        for (&n, p) in planned.enumerate() {
+            registered[p] %= n + 1;
+        }
+        let mut observed = board.clone();
+        observed.poke_all(&registered);
+
+        let dropped = board.dropped_pokes(&planned, &observed);
+        prop_assert!(dropped.is_some(), "{:?} left {}", registered, observed);
+        let dropped = dropped.unwrap();
+        let mut resent = observed.clone();
+        resent.poke_all(&dropped);
+        prop_assert!(resent.is_solved());
+        for (&n, p) in dropped.enumerate() {
+            prop_assert!(n <= planned[p]);
+        }
+    }
+}
@@ -0,0 +1,39 @@
+#![cfg(feature = "cli")]
+
+use std::process::Command;
+
+/// What `--explain` prints for `board`, without the trailing spaces of the
+/// drawn boards.
+fn explain(board: &str) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_solve_arrow_puzzle"))
+        .args(["--explain", board])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| line.trim_end().to_owned() + "\n")
+        .collect()
+}
+
+/// The solved board with the center poked once, which takes poking it five
+/// more times.
+#[test]
+fn explains_the_steps_of_solving_a_board() {
+    let want: String = include_str!("fixtures/explain.txt")
+        .lines()
+        .map(|line| line.trim_end().to_owned() + "\n")
+        .collect();
+    assert_eq!(explain("0000 00000 001100 0011100 001100 00000 0000"), want);
+}
+
+#[test]
+fn says_when_no_pokes_solve_the_board() {
+    let explained = explain("1000000000000000000000000000000000000");
+    assert!(
+        explained.contains("\nno pokes solve this board"),
+        "{}",
+        explained
+    );
+}
@@ -0,0 +1,44 @@
+#![cfg(feature = "proptest")]
+
+use proptest::{bool::weighted, collection::vec, prelude::*};
+use solve_arrow_puzzle::{
+    expert::{arbitrary::solvable_board, Arrow, PartialBoard},
+    hex::Hex,
+};
+
+/// Which cells to hide, each with probability `p`.
+fn hidden(p: f64) -> impl Strategy<Value = Hex<bool>> {
+    vec(weighted(p), Hex::<bool>::POSITIONS.len()).prop_map(|hidden| {
+        let mut hidden = hidden.into_iter();
+        Hex::from_fn(|_, _| hidden.next().unwrap())
+    })
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(2000))]
+
+    #[test]
+    fn partial_plans_solve_every_cell_but_the_needed_ones(
+        board in solvable_board(),
+        hidden in hidden(0.2),
+    ) {
+        let mut arrows = PartialBoard::from(&board).arrows().clone();
+        for (&hide, p) in hidden.enumerate() {
+            if hide {
+                arrows[p] = None;
+            }
+        }
+
+        let plan = PartialBoard::new(arrows).solve();
+        prop_assert!(plan.is_some());
+        let plan = plan.unwrap();
+        let mut after = board.clone();
+        after.poke_all(&plan.pokes);
+        for (&arrow, p) in after.arrows().enumerate() {
+            prop_assert!(hidden[p] || !plan.needed[p], "{:?} is known", p);
+            if !plan.needed[p] {
+                prop_assert_eq!(arrow, Arrow(0), "{:?}", p);
+            }
+        }
+    }
+}
@@ -0,0 +1,223 @@
+#![cfg(feature = "device")]
+
+use std::{
+    collections::VecDeque,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use solve_arrow_puzzle::{
+    app::{
+        play, DetectError, Detection, Device, DeviceError, Frame, Plan, PlayError, ScreenState,
+        WaitingFor,
+    },
+    expert::Board,
+    hex::Hex,
+    observer::{PlayObserver, Stats},
+};
+
+/// Shows `screen` until pressing back a few times brings up `after_back`, and
+/// then the screens in `after_taps` one tap on the board at a time, on a clock
+/// that only moves while waiting for frames.
+struct FakeDevice<'a> {
+    started_at: Instant,
+    elapsed: Duration,
+    sequence: u64,
+    screen: ScreenState,
+    after_back: Option<(usize, ScreenState)>,
+    after_taps: VecDeque<ScreenState>,
+    back_presses: usize,
+    taps: Vec<Hex<usize>>,
+    /// Set on the tap on the board after the last of `after_taps`, which ends
+    /// the game.
+    stop: &'a AtomicBool,
+}
+
+impl<'a> FakeDevice<'a> {
+    fn new(screen: ScreenState, stop: &'a AtomicBool) -> FakeDevice<'a> {
+        FakeDevice {
+            started_at: Instant::now(),
+            elapsed: Duration::ZERO,
+            sequence: 0,
+            screen,
+            after_back: None,
+            after_taps: VecDeque::new(),
+            back_presses: 0,
+            taps: vec![],
+            stop,
+        }
+    }
+
+    fn frame(&self) -> Frame {
+        Frame {
+            sequence: self.sequence,
+            captured_at: self.now(),
+        }
+    }
+
+    fn next_frame(&mut self, after: Duration) -> Frame {
+        self.elapsed += after;
+        self.sequence += 1;
+        self.frame()
+    }
+}
+
+impl Device for FakeDevice<'_> {
+    fn now(&self) -> Instant {
+        self.started_at + self.elapsed
+    }
+
+    fn detect_screen(&mut self) -> Result<Detection, DetectError> {
+        Ok(Detection {
+            frame: self.frame(),
+            screen: self.screen.clone(),
+        })
+    }
+
+    fn wait_for_next_frame(&mut self, _: &Frame, timeout: Duration) -> Result<(), DeviceError> {
+        self.next_frame(timeout);
+        Ok(())
+    }
+
+    fn wait_for_frame_after(&mut self, _: Instant) -> Result<Frame, DeviceError> {
+        Ok(self.next_frame(Duration::from_millis(100)))
+    }
+
+    fn tap_board(&mut self, taps: Hex<usize>) -> Result<(), DeviceError> {
+        self.taps.push(taps);
+        match self.after_taps.pop_front() {
+            Some(screen) => self.screen = screen,
+            None => self.stop.store(true, Ordering::Relaxed),
+        }
+        Ok(())
+    }
+
+    fn tap_claim_button(&mut self) -> Result<(), DeviceError> {
+        Ok(())
+    }
+
+    fn press_back(&mut self) -> Result<(), DeviceError> {
+        self.back_presses += 1;
+        if let Some((presses, _)) = &self.after_back {
+            if self.back_presses == *presses {
+                self.screen = self.after_back.take().unwrap().1;
+            }
+        }
+        Ok(())
+    }
+
+    fn reconnect(&mut self) -> Result<(), DeviceError> {
+        Ok(())
+    }
+}
+
+#[test]
+fn presses_back_a_few_times_on_an_unknown_screen_before_giving_up() {
+    let stop = AtomicBool::new(false);
+    let mut device = FakeDevice::new(ScreenState::Unknown, &stop);
+    let played = play(&mut device, (), &stop);
+    assert!(
+        matches!(played, Err(PlayError::Timeout(WaitingFor::Board, _))),
+        "{:?}",
+        played
+    );
+    assert_eq!(device.back_presses, 3);
+}
+
+#[test]
+fn plays_the_board_that_pressing_back_on_an_unknown_screen_brings_up() {
+    let stop = AtomicBool::new(false);
+    let mut device = FakeDevice::new(ScreenState::Unknown, &stop);
+    let board = "0521 11155 553445 5400142 004555 53302 5304"
+        .parse()
+        .unwrap();
+    device.after_back = Some((
+        2,
+        ScreenState::ExpertBoard {
+            board,
+            claim_button: false,
+        },
+    ));
+    play(&mut device, (), &stop).unwrap();
+    assert_eq!(device.back_presses, 2);
+    assert_eq!(device.taps.len(), 1);
+}
+
+/// The plans the player sent, and whether it said the board was poked before.
+#[derive(Default)]
+struct Solves(Vec<(Plan, bool)>);
+
+impl PlayObserver for Solves {
+    fn on_solve(&mut self, _now: Instant, plan: &Plan, again: bool) {
+        self.0.push((plan.clone(), again));
+    }
+}
+
+fn expert_board(board: Board) -> ScreenState {
+    ScreenState::ExpertBoard {
+        board,
+        claim_button: false,
+    }
+}
+
+#[test]
+fn resends_only_the_pokes_that_got_dropped() {
+    let board: Board = "0521 11155 553445 5400142 004555 53302 5304"
+        .parse()
+        .unwrap();
+    let planned = board.clone().solve();
+    let (_, dropped_at) = planned.enumerate().find(|(&n, _)| n > 0).unwrap();
+    let mut registered = planned.clone();
+    registered[dropped_at] = 0;
+    let mut observed = board.clone();
+    observed.poke_all(&registered);
+
+    let stop = AtomicBool::new(false);
+    let mut device = FakeDevice::new(expert_board(board), &stop);
+    device.after_taps.push_back(expert_board(observed.clone()));
+    let mut solves = Solves::default();
+    let mut stats = Stats::new(device.now());
+    play(&mut device, (&mut solves, &mut stats), &stop).unwrap();
+
+    let [(first, false), (second, true)] = &solves.0[..] else {
+        panic!("want a plan and another one, but got {:?}", solves.0);
+    };
+    assert!(!first.resend);
+    assert!(second.resend);
+    assert_eq!((stats.re_solves, stats.full_re_solves), (1, 0));
+    assert_eq!(
+        stats.pokes_dropped.iter().sum::<usize>(),
+        planned[dropped_at]
+    );
+    assert_eq!(second.board, observed);
+    let mut dropped = Hex::from_fn(|_, _| 0);
+    dropped[dropped_at] = planned[dropped_at];
+    assert_eq!(second.pokes, dropped);
+}
+
+#[test]
+fn a_board_that_dropped_pokes_dont_explain_is_solved_from_scratch() {
+    let board: Board = "0521 11155 553445 5400142 004555 53302 5304"
+        .parse()
+        .unwrap();
+    let planned = board.clone().solve();
+    // Another puzzle, as if the board got replaced while poking it
+    let other: Board = "1111111111111111111111111111111111111".parse().unwrap();
+    assert!(other.is_solvable());
+    assert_eq!(board.dropped_pokes(&planned, &other), None);
+
+    let stop = AtomicBool::new(false);
+    let mut device = FakeDevice::new(expert_board(board), &stop);
+    device.after_taps.push_back(expert_board(other.clone()));
+    let mut solves = Solves::default();
+    let mut stats = Stats::new(device.now());
+    play(&mut device, (&mut solves, &mut stats), &stop).unwrap();
+
+    let [(_, false), (second, true)] = &solves.0[..] else {
+        panic!("want a plan and another one, but got {:?}", solves.0);
+    };
+    assert!(!second.resend);
+    assert_eq!((stats.re_solves, stats.full_re_solves), (0, 1));
+    assert_eq!(stats.pokes_dropped.iter().sum::<usize>(), 0);
+    assert_eq!(second.pokes, other.solve());
+}
@@ -0,0 +1,94 @@
+#!/usr/bin/env python3
+"""Smoke test for the Python bindings. Builds the extension module with the
+`python` feature, imports it and checks that its plans solve boards.
+
+    python3 tests/python_smoke_test.py
+"""
+
+import json
+import random
+import shutil
+import subprocess
+import sys
+import tempfile
+from pathlib import Path
+
+ROOT = Path(__file__).resolve().parent.parent
+
+
+def build_module(out_dir):
+    command = [
+        "cargo", "rustc", "--quiet", "--lib", "--no-default-features",
+        "--features", "python", "--crate-type", "cdylib",
+    ]
+    if sys.platform == "darwin":
+        command += ["--", "-C", "link-arg=-undefined", "-C", "link-arg=dynamic_lookup"]
+    subprocess.run(command, cwd=ROOT, check=True)
+
+    metadata = subprocess.run(
+        ["cargo", "metadata", "--format-version", "1", "--no-deps"],
+        cwd=ROOT, check=True, capture_output=True, text=True,
+    )
+    target_dir = Path(json.loads(metadata.stdout)["target_directory"])
+    library = {
+        "darwin": "libsolve_arrow_puzzle.dylib",
+        "win32": "solve_arrow_puzzle.dll",
+    }.get(sys.platform, "libsolve_arrow_puzzle.so")
+    module = "solve_arrow_puzzle.pyd" if sys.platform == "win32" else "solve_arrow_puzzle.so"
+    shutil.copy(target_dir / "debug" / library, Path(out_dir) / module)
+
+
+ROWS = [(y, range(max(0, y - 3), min(7, y + 4))) for y in range(7)]
+
+
+def poke_all(arrows, taps):
+    """The same pokes as `Board::poke`, on rows of arrows."""
+    cells = {(x, y): a for y, xs in ROWS for x, a in zip(xs, arrows[y])}
+    for y, xs in ROWS:
+        for x, n in zip(xs, taps[y]):
+            for dx, dy in [(-1, -1), (0, -1), (-1, 0), (0, 0), (1, 0), (0, 1), (1, 1)]:
+                if (x + dx, y + dy) in cells:
+                    cells[x + dx, y + dy] = (cells[x + dx, y + dy] + n) % 6
+    return [[cells[x, y] for x in xs] for y, xs in ROWS]
+
+
+def main():
+    with tempfile.TemporaryDirectory() as out_dir:
+        build_module(out_dir)
+        sys.path.insert(0, out_dir)
+        from solve_arrow_puzzle import Board
+
+        solved = Board("0" * 37)
+        assert solved.is_solved()
+        assert solved.solve() == [[0] * len(xs) for _, xs in ROWS]
+
+        rng = random.Random(0)
+        for _ in range(100):
+            # Scrambled from a solved board, since not every board is solvable
+            scramble = [[rng.randrange(6) for _ in xs] for _, xs in ROWS]
+            arrows = poke_all([[0] * len(xs) for _, xs in ROWS], scramble)
+            board = Board(arrows)
+            assert board.arrows() == arrows
+            assert Board(sum(arrows, [])) == board
+            assert Board(" ".join("".join(map(str, row)) for row in arrows)) == board
+            assert len(str(board).split()) == 37
+            assert eval(repr(board), {"Board": Board}) == board
+
+            taps = board.solve()
+            assert [len(row) for row in taps] == [4, 5, 6, 7, 6, 5, 4]
+            assert all(0 <= n < 6 for row in taps for n in row)
+            assert Board(poke_all(arrows, taps)).is_solved()
+
+        for bad in ["0" * 36, "0" * 36 + "6", "0" * 36 + "x", [[0] * 37], [0] * 38]:
+            try:
+                Board(bad)
+            except ValueError:
+                pass
+            else:
+                raise AssertionError(f"Board({bad!r}) should fail")
+
+    print("python bindings ok")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,152 @@
+#![cfg(feature = "device")]
+
+use std::{fs::File, io::BufReader, path::Path};
+
+use solve_arrow_puzzle::{
+    app::ScreenState,
+    device::{Profile, ScreenTemplates, Transform, Vec2},
+};
+
+const SCREEN_WIDTH: usize = 1440;
+const SCREEN_HEIGHT: usize = 3120;
+
+/// The profile of the phone the screenshots in `references/` were taken on.
+fn profile() -> Profile {
+    Profile {
+        screen_width: SCREEN_WIDTH,
+        screen_height: SCREEN_HEIGHT,
+        claim_button: Vec2::new(721.0, 2750.0),
+        transform: Transform::new(Vec2::new(721.5, 1178.5), Vec2::new(721.5, 2430.5)),
+        orientation: None,
+        templates: ScreenTemplates::default(),
+    }
+}
+
+/// The lumas of a screenshot in `references/`, converted the same way as the
+/// ones `adb exec-out screencap` takes.
+fn reference(name: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("references")
+        .join(name);
+    let file = BufReader::new(File::open(&path).unwrap());
+    let mut reader = png::Decoder::new(file).read_info().unwrap();
+    let mut rgbas = vec![0u8; reader.output_buffer_size().unwrap()];
+    let info = reader.next_frame(&mut rgbas).unwrap();
+    assert_eq!(
+        (info.width as usize, info.height as usize, info.color_type),
+        (SCREEN_WIDTH, SCREEN_HEIGHT, png::ColorType::Rgba),
+        "{}",
+        path.display()
+    );
+    rgbas
+        .chunks_exact(4)
+        .map(|rgba| {
+            let [r, g, b] = [rgba[0], rgba[1], rgba[2]].map(u32::from);
+            (16 + ((66 * r + 129 * g + 25 * b + 128) >> 8)) as u8
+        })
+        .collect()
+}
+
+fn detect(lumas: &[u8]) -> ScreenState {
+    profile()
+        .detect_screen(lumas, SCREEN_WIDTH, SCREEN_HEIGHT, 1)
+        .unwrap()
+}
+
+#[test]
+fn detects_the_expert_board() {
+    let screen = detect(&reference("expert_not_solved.png"));
+    let ScreenState::ExpertBoard {
+        board,
+        claim_button,
+    } = screen
+    else {
+        panic!("want the expert board, but got {}", screen);
+    };
+    assert_eq!(
+        board,
+        "0521 11155 553445 5400142 004555 53302 5304"
+            .parse()
+            .unwrap()
+    );
+    assert!(!claim_button);
+}
+
+#[test]
+fn detects_the_normal_board() {
+    assert_eq!(
+        detect(&reference("normal_solved.png")),
+        ScreenState::NormalBoard
+    );
+}
+
+#[test]
+fn an_unreadable_expert_board_is_unknown() {
+    let mut lumas = reference("expert_not_solved.png");
+    // Blacks out the top arrow
+    for y in 1080..1280 {
+        lumas[SCREEN_WIDTH * y..][620..820].fill(0);
+    }
+    assert_eq!(detect(&lumas), ScreenState::Unknown);
+}
+
+/// There's no screenshot of a solved expert board, so this pastes the claim
+/// button of the normal one over the "I Give Up!" button of the expert one,
+/// which is 99 pixels further down.
+#[test]
+fn detects_the_claim_button() {
+    let mut lumas = reference("expert_not_solved.png");
+    let normal = reference("normal_solved.png");
+    let button = SCREEN_WIDTH * 2577..SCREEN_WIDTH * 2745;
+    let offset = SCREEN_WIDTH * 99;
+    lumas[button.start + offset..button.end + offset].copy_from_slice(&normal[button]);
+    let screen = detect(&lumas);
+    assert!(
+        matches!(
+            screen,
+            ScreenState::ExpertBoard {
+                claim_button: true,
+                ..
+            }
+        ),
+        "want the expert board with the claim button, but got {}",
+        screen
+    );
+}
+
+/// The blacked out arrow of the test above stands in for a popup, which
+/// there's no screenshot of.
+#[test]
+fn detects_a_popup_from_templates_read_from_json() {
+    let templates: ScreenTemplates = serde_json::from_str(
+        r#"{"popup": [{
+            "top_left": {"x": 620.0, "y": 1080.0},
+            "bottom_right": {"x": 820.0, "y": 1280.0},
+            "lumas": {"start": 0, "end": 10}
+        }]}"#,
+    )
+    .unwrap();
+    assert_eq!(
+        templates.normal_board,
+        ScreenTemplates::default().normal_board
+    );
+    let profile = Profile {
+        templates,
+        ..profile()
+    };
+
+    let mut lumas = reference("expert_not_solved.png");
+    for y in 1080..1280 {
+        lumas[SCREEN_WIDTH * y..][620..820].fill(0);
+    }
+    let detect = |lumas: &[u8]| {
+        profile
+            .detect_screen(lumas, SCREEN_WIDTH, SCREEN_HEIGHT, 1)
+            .unwrap()
+    };
+    assert_eq!(detect(&lumas), ScreenState::Popup);
+    assert_eq!(
+        detect(&reference("normal_solved.png")),
+        ScreenState::NormalBoard
+    );
+}
@@ -0,0 +1,35 @@
+#![cfg(feature = "device")]
+
+use std::{
+    env::temp_dir,
+    fs::{read_dir, remove_dir_all},
+    process,
+    time::Instant,
+};
+
+use solve_arrow_puzzle::{app::Frame, session::SessionRecorder};
+
+#[test]
+fn only_copies_the_lumas_of_new_frames_that_get_written() {
+    let dir = temp_dir().join(format!("session-test-{}", process::id()));
+    let recorder = SessionRecorder::create(&dir, 2).unwrap();
+    let captured_at = Instant::now();
+    let frames = [0, 0, 1, 2, 2, 3, 4].map(|sequence| Frame {
+        sequence,
+        captured_at,
+    });
+
+    let mut wanted = vec![];
+    for frame in &frames {
+        let wants_lumas = recorder.wants_lumas(frame);
+        wanted.push(wants_lumas);
+        let lumas = wants_lumas.then_some(&[0u8; 4][..]);
+        recorder.record_frame(frame, 2, 2, 1, lumas);
+    }
+    recorder.finish().unwrap();
+    let written = read_dir(dir.join("frames")).unwrap().count();
+    remove_dir_all(&dir).unwrap();
+
+    assert_eq!(wanted, [true, false, false, true, false, false, true]);
+    assert_eq!(written, 3);
+}
@@ -0,0 +1,48 @@
+#![cfg(feature = "proptest")]
+
+use std::collections::HashSet;
+
+use proptest::prelude::*;
+use solve_arrow_puzzle::{
+    expert::{arbitrary::solvable_board, Arrow, Board, Kernel, KERNELS},
+    hex::{positions::D3, Hex},
+};
+
+proptest! {
+    // Every case goes through all the solutions of a board
+    #![proptest_config(ProptestConfig::with_cases(20))]
+
+    #[test]
+    fn solutions_are_distinct_plans_that_solve_the_board(board in solvable_board()) {
+        let solutions: Vec<Hex<usize>> = board.solutions().collect();
+
+        prop_assert_eq!(
+            solutions.len(),
+            KERNELS.iter().map(Kernel::size).product::<usize>()
+        );
+        prop_assert_eq!(&solutions[0], &board.clone().solve());
+        for plan in &solutions {
+            let mut after = board.clone();
+            after.poke_all(plan);
+            prop_assert!(after.is_solved());
+        }
+        let distinct: HashSet<Vec<usize>> = solutions
+            .iter()
+            .map(|plan| plan.enumerate().map(|(&n, _)| n).collect())
+            .collect();
+        prop_assert_eq!(distinct.len(), solutions.len());
+    }
+}
+
+/// Turning any arrow but the center one alone leaves a board that no pokes
+/// solve, and so that can't come from the game.
+#[test]
+fn a_board_with_one_arrow_turned_is_unsolvable() {
+    for &p in Hex::<Arrow>::POSITIONS.iter() {
+        let mut arrows = Hex::from_fn(|_, _| Arrow(0));
+        arrows[p] = Arrow(1);
+        let board = Board::new(arrows);
+        assert_eq!(board.is_solvable(), p == D3, "{:?}", p);
+        assert_eq!(board.solutions().len() > 0, p == D3, "{:?}", p);
+    }
+}
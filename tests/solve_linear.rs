@@ -0,0 +1,13 @@
+#![cfg(feature = "proptest")]
+
+use proptest::prelude::*;
+use solve_arrow_puzzle::expert::Board;
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(2000))]
+
+    #[test]
+    fn solve_linear_gives_the_same_plans_as_solve(board in any::<Board>()) {
+        prop_assert_eq!(board.clone().solve_linear(), board.solve());
+    }
+}
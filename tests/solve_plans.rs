@@ -0,0 +1,23 @@
+#![cfg(feature = "solver")]
+
+use solve_arrow_puzzle::expert::Board;
+
+/// Boards and the plans that `Board::solve` gave for them before boards got
+/// packed into bits, one board per line as 37 arrows, a space and 37 poke
+/// counts. The first 500 boards are random and the rest are scrambled solved
+/// boards.
+const PLANS: &str = include_str!("fixtures/solve_plans.txt");
+
+#[test]
+fn solve_gives_the_same_plans_as_before() {
+    for (i, line) in PLANS.lines().enumerate() {
+        let (board, want) = line.split_once(' ').unwrap();
+        let board: Board = board.parse().unwrap();
+        let got: String = board
+            .solve()
+            .enumerate()
+            .map(|(n, _)| n.to_string())
+            .collect();
+        assert_eq!(got, want, "line {}", i + 1);
+    }
+}
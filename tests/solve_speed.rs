@@ -0,0 +1,174 @@
+#![cfg(feature = "solver")]
+
+//! Compares how fast the solvers are. Only meaningful in release mode:
+//!
+//! ```sh
+//! cargo test --release --test solve_speed -- --ignored --nocapture
+//! ```
+
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+use solve_arrow_puzzle::{
+    expert::{Arrow, Board, PackedBoard},
+    hex::{positions::*, Hex},
+};
+
+/// The same boards as `solve_plans`, and the plans the old solver gave for
+/// them.
+const PLANS: &str = include_str!("fixtures/solve_plans.txt");
+
+const ROUNDS: usize = 20;
+
+fn distance(from: Arrow, to: Arrow) -> usize {
+    (to.0 + 6 - from.0) as usize % 6
+}
+
+fn poke(arrows: &mut Hex<Arrow>, p: Position) {
+    const DS: [(isize, isize); 7] = [(-1, -1), (0, -1), (-1, 0), (0, 0), (1, 0), (0, 1), (1, 1)];
+    let (x, y) = p.as_xy();
+    for (dx, dy) in DS {
+        let (Some(x), Some(y)) = (x.checked_add_signed(dx), y.checked_add_signed(dy)) else {
+            continue;
+        };
+        if let Some(a) = arrows.at_mut(x, y) {
+            a.0 = (a.0 + 1) % 6;
+        }
+    }
+}
+
+/// The solver from before boards got packed into bits, which pokes a
+/// [`Hex`] of arrows one arrow at a time and solves every orientation on a
+/// rotated and flipped copy of the board.
+fn old_solve(mut arrows: Hex<Arrow>) -> Hex<usize> {
+    const PARTIAL_SOLVE_MOVES: [(Position, Position); 30] = [
+        (A0, B1),
+        (A1, B2),
+        (A2, B3),
+        (A3, B4),
+        (B0, C1),
+        (C0, D1),
+        (D0, E1),
+        (B1, C2),
+        (B2, C3),
+        (B3, C4),
+        (B4, C5),
+        (C1, D2),
+        (D1, E2),
+        (E1, F2),
+        (C2, D3),
+        (C3, D4),
+        (C4, D5),
+        (C5, D6),
+        (D2, E3),
+        (E2, F3),
+        (F2, G3),
+        (D3, E4),
+        (D4, E5),
+        (D5, E6),
+        (E3, F4),
+        (F3, G4),
+        (E4, F5),
+        (E5, F6),
+        (F4, G5),
+        (F5, G6),
+    ];
+
+    fn poke_n(arrows: &mut Hex<Arrow>, counts: &mut Hex<usize>, p: Position, n: usize) {
+        counts[p] += n;
+        for _ in 0..n {
+            poke(arrows, p);
+        }
+    }
+
+    fn partially_solve(arrows: &mut Hex<Arrow>, counts: &mut Hex<usize>) {
+        for (solvee, p) in PARTIAL_SOLVE_MOVES {
+            let n = distance(arrows[solvee], Arrow(0));
+            poke_n(arrows, counts, p, n);
+        }
+    }
+
+    fn solve_this_orientation(mut arrows: Hex<Arrow>) -> Hex<usize> {
+        let mut counts = Hex::from_fn(|_, _| 0);
+        partially_solve(&mut arrows, &mut counts);
+        let (d6, e6, f6) = (arrows[D6], arrows[E6], arrows[F6]);
+        let b_d = distance(e6, Arrow(0));
+        let c = if (d6.0 + f6.0) % 2 == 0 { 0 } else { 3 };
+        for (p, n) in [
+            (A0, distance(Arrow(0), e6) + distance(d6, Arrow(0))),
+            (A1, b_d),
+            (A2, c),
+            (A3, b_d),
+        ] {
+            poke_n(&mut arrows, &mut counts, p, n);
+        }
+        partially_solve(&mut arrows, &mut counts);
+        for (n, _) in counts.enumerate_mut() {
+            *n %= 6;
+        }
+        counts
+    }
+
+    let mut best: Option<(usize, Hex<usize>)> = None;
+    for flipped in [false, true] {
+        for rotation in 0..6 {
+            let mut counts = solve_this_orientation(arrows.clone());
+            for _ in 0..(6 - rotation) % 6 {
+                counts.rotate_60_cw();
+            }
+            if flipped {
+                counts.flip_horizontally();
+            }
+            let total = counts.enumerate().map(|(&n, _)| n).sum::<usize>();
+            if best.as_ref().is_none_or(|&(min, _)| total < min) {
+                best = Some((total, counts));
+            }
+            arrows.rotate_60_cw();
+        }
+        arrows.flip_horizontally();
+    }
+    best.unwrap().1
+}
+
+/// The mean time `solve` takes per board.
+fn time<T, F>(boards: &[Board], mut solve: F) -> Duration
+where
+    F: FnMut(&Board) -> T,
+{
+    let started_at = Instant::now();
+    for _ in 0..ROUNDS {
+        for board in boards {
+            black_box(solve(black_box(board)));
+        }
+    }
+    started_at.elapsed() / (ROUNDS * boards.len()) as u32
+}
+
+#[test]
+#[ignore = "a benchmark, run it in release mode"]
+fn packed_solvers_are_faster_than_the_old_one() {
+    let boards: Vec<Board> = PLANS
+        .lines()
+        .map(|line| line.split_once(' ').unwrap().0.parse().unwrap())
+        .collect();
+    for (board, line) in boards.iter().zip(PLANS.lines()) {
+        let old: String = old_solve(board.arrows().clone())
+            .enumerate()
+            .map(|(n, _)| n.to_string())
+            .collect();
+        assert_eq!(old, line.split_once(' ').unwrap().1);
+    }
+
+    let old = time(&boards, |board| old_solve(board.arrows().clone()));
+    let packed = time(&boards, |board| board.clone().solve());
+    let packed_only = time(&boards, |board| PackedBoard::from(board).solve());
+    let linear = time(&boards, |board| PackedBoard::from(board).solve_linear());
+    println!("old solver:                {:?} per board", old);
+    println!("Board::solve:              {:?} per board", packed);
+    println!("PackedBoard::solve:        {:?} per board", packed_only);
+    println!("PackedBoard::solve_linear: {:?} per board", linear);
+    assert!(packed < old);
+    assert!(linear < packed_only);
+}
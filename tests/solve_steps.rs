@@ -0,0 +1,83 @@
+#![cfg(feature = "proptest")]
+
+use std::collections::HashMap;
+
+use proptest::prelude::*;
+use solve_arrow_puzzle::{
+    expert::{arbitrary::solvable_board, Group, Step},
+    hex::{positions::Position, Hex},
+};
+
+/// The groups as drawn in the diagram on [`Group`], which lines the cells up
+/// the same way as [`Hex::visualize`].
+fn diagram() -> Hex<String> {
+    let diagram: Vec<&str> = include_str!("../src/expert.rs")
+        .lines()
+        .skip_while(|line| *line != "/// | Groups")
+        .skip(1)
+        .take_while(|line| line.starts_with("/// |"))
+        .map(|line| line.strip_prefix("/// | ").unwrap_or(""))
+        .collect();
+    let mut cells = Hex::<Position>::POSITIONS.into_iter();
+    let positions = Hex::from_fn(|_, _| cells.next().unwrap());
+    let layout = positions.visualize(|p| p.to_string().into());
+    assert_eq!(layout.lines().count(), diagram.len());
+
+    let mut groups = HashMap::new();
+    for (cells, labels) in layout.lines().zip(diagram) {
+        for (i, _) in cells.match_indices(|c: char| c.is_ascii_uppercase()) {
+            groups.insert(&cells[i..i + 2], labels[i..i + 2].to_owned());
+        }
+    }
+    assert_eq!(groups.len(), Hex::<Position>::POSITIONS.len());
+    Hex::from_fn(|x, y| groups[&*positions.at(x, y).unwrap().to_string()].clone())
+}
+
+/// The diagram in all 12 orientations a board can get solved in.
+fn diagrams() -> Vec<Hex<String>> {
+    let mut groups = diagram();
+    let mut diagrams = vec![];
+    for _ in 0..2 {
+        for _ in 0..6 {
+            diagrams.push(groups.clone());
+            groups.rotate_60_cw();
+        }
+        groups.flip_horizontally();
+    }
+    diagrams
+}
+
+proptest! {
+    #[test]
+    fn steps_poke_the_plan_of_solve_and_solve_the_board(board in solvable_board()) {
+        let steps: Vec<Step> = board.solve_steps().collect();
+        if let Some(last) = steps.last() {
+            prop_assert!(last.board.is_solved(), "{}", last.board);
+        }
+        let mut pokes = Hex::from_fn(|_, _| 0);
+        for step in &steps {
+            pokes[step.poke] += 1;
+        }
+        for (n, _) in pokes.enumerate_mut() {
+            *n %= 6;
+        }
+        prop_assert_eq!(pokes, board.solve());
+    }
+
+    /// The fixup pokes cells of G0, which partially solving aligns first.
+    #[test]
+    fn steps_are_labeled_with_the_groups_of_the_diagram(board in solvable_board()) {
+        let steps: Vec<Step> = board.solve_steps().collect();
+        let label = |step: &Step| match step.group {
+            Group::Fixup => "G0".to_owned(),
+            group => group.to_string(),
+        };
+        prop_assert!(
+            diagrams()
+                .iter()
+                .any(|groups| steps.iter().all(|step| groups[step.poke] == label(step))),
+            "{:?}",
+            steps.iter().map(|step| (step.poke, step.group)).collect::<Vec<_>>()
+        );
+    }
+}
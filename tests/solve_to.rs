@@ -0,0 +1,47 @@
+#![cfg(feature = "proptest")]
+
+use proptest::prelude::*;
+use solve_arrow_puzzle::{
+    expert::{arbitrary::pokes, Arrow, Board},
+    hex::{
+        positions::{Position, D3},
+        Hex,
+    },
+};
+
+fn turned(board: &Board, p: Position) -> Board {
+    let mut arrows = board.arrows().clone();
+    arrows[p] = Arrow((arrows[p].0 + 1) % 6);
+    Board::new(arrows)
+}
+
+proptest! {
+    #[test]
+    fn solve_to_reaches_any_target_that_pokes_reach(
+        board in any::<Board>(),
+        scramble in pokes(5),
+    ) {
+        let mut target = board.clone();
+        target.poke_all(&scramble);
+        prop_assert!(board.can_reach(&target));
+        let plan = board.clone().solve_to(&target);
+        let mut after = board.clone();
+        after.poke_all(&plan);
+        prop_assert!(after.is_solved_to(&target));
+    }
+
+    /// Turning one arrow of a reachable target turns it into an unreachable
+    /// one, the same way it makes a solved board unsolvable, except for the
+    /// center arrow.
+    #[test]
+    fn a_target_with_one_more_arrow_turned_is_unreachable(
+        board in any::<Board>(),
+        scramble in pokes(5),
+    ) {
+        let mut target = board.clone();
+        target.poke_all(&scramble);
+        for &p in Hex::<Arrow>::POSITIONS.iter() {
+            prop_assert_eq!(board.can_reach(&turned(&target, p)), p == D3, "{:?}", p);
+        }
+    }
+}